@@ -0,0 +1,298 @@
+use std::{ops::Deref, time::Duration};
+
+use jiff::Timestamp;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+
+use crate::{hash, Edge, Node, NodeError, Session, Terfer, TerferGraph};
+
+/// What principals other than a node's owner may do with it. The owner can
+/// always read and write. Nodes without an owner are open to everyone, but
+/// only a graph without a principal can give them one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Permissions {
+    pub const PRIVATE: Permissions = Permissions {
+        read: false,
+        write: false,
+    };
+    pub const READ_ONLY: Permissions = Permissions {
+        read: true,
+        write: false,
+    };
+    pub const PUBLIC: Permissions = Permissions {
+        read: true,
+        write: true,
+    };
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Permissions::PUBLIC
+    }
+}
+
+enum Access {
+    Read,
+    Write,
+    Owner,
+}
+
+fn allows(principal: Option<&str>, node: &Node, access: Access) -> bool {
+    let Some(principal) = principal else {
+        return true;
+    };
+    match node.owner.as_deref() {
+        Some(owner) if owner == principal => true,
+        Some(_) => match access {
+            Access::Read => node.permissions.read,
+            Access::Write => node.permissions.write,
+            Access::Owner => false,
+        },
+        None => !matches!(access, Access::Owner),
+    }
+}
+
+pub(crate) fn can_read(graph: &TerferGraph, node: &Node) -> bool {
+    readable_by(graph.principal.as_deref(), node)
+}
+
+/// Like [`can_read`], for any principal rather than the graph's own.
+pub(crate) fn readable_by(principal: Option<&str>, node: &Node) -> bool {
+    allows(principal, node, Access::Read)
+}
+
+fn check(graph: &TerferGraph, index: NodeIndex, access: Access) -> Result<(), NodeError> {
+    let node = graph
+        .graph
+        .node_weight(index)
+        .ok_or(NodeError::NotFound(index))?;
+    if allows(graph.principal.as_deref(), node, access) {
+        Ok(())
+    } else {
+        Err(NodeError::PermissionDenied(index))
+    }
+}
+
+pub(crate) fn check_write(graph: &TerferGraph, index: NodeIndex) -> Result<(), NodeError> {
+    check(graph, index, Access::Write)
+}
+
+/// Gives nodes created while a principal is set to that principal, private
+/// until the owner says otherwise.
+pub(crate) fn claim(graph: &TerferGraph, node: &mut Node) {
+    if let Some(principal) = &graph.principal {
+        node.owner = Some(principal.clone());
+        node.permissions = Permissions::PRIVATE;
+    }
+}
+
+pub(crate) fn set_owner(
+    graph: &mut TerferGraph,
+    node: NodeIndex,
+    owner: Option<String>,
+    permissions: Permissions,
+) -> Result<(), NodeError> {
+    check(graph, node, Access::Owner)?;
+    let weight = graph.node_mut(node);
+    weight.owner = owner;
+    weight.permissions = permissions;
+    hash::touch(graph, node);
+    Ok(())
+}
+
+/// A graph acting on behalf of a principal other than its own, returned by
+/// [`Terfer::as_principal`](crate::Terfer::as_principal).
+///
+/// Reads through the view and the changes made with its methods are checked
+/// against that principal exactly as for a graph made with
+/// [`Terfer::new_tg_as`](crate::Terfer::new_tg_as), but they act on the shared
+/// graph itself. Its own principal is back in place once the view is dropped.
+pub struct Scoped<'a> {
+    graph: &'a mut TerferGraph,
+    previous: Option<String>,
+}
+
+impl<'a> Scoped<'a> {
+    pub(crate) fn new(graph: &'a mut TerferGraph, principal: &str) -> Self {
+        let previous = graph.principal.replace(principal.to_string());
+        Scoped { graph, previous }
+    }
+
+    pub fn add_node(&mut self, node: Node) -> NodeIndex {
+        self.graph.add_node(node)
+    }
+
+    pub fn add_edge(&mut self, a: NodeIndex, b: NodeIndex, edge: Edge) -> Result<(), NodeError> {
+        self.graph.add_edge(a, b, edge)
+    }
+
+    pub fn connect_by_alias(&mut self, a: &str, b: &str, edge: Edge) -> Result<(), NodeError> {
+        self.graph.connect_by_alias(a, b, edge)
+    }
+
+    pub fn set_alias(&mut self, node: NodeIndex, alias: &str) -> Result<(), NodeError> {
+        self.graph.set_alias(node, alias)
+    }
+
+    pub fn update(&mut self, node: NodeIndex, value: String) -> Result<(), NodeError> {
+        self.graph.update(node, value)
+    }
+
+    pub fn delete(&mut self, node: NodeIndex) -> Result<(), NodeError> {
+        self.graph.delete(node)
+    }
+
+    pub fn restore(&mut self, node: NodeIndex) -> Result<(), NodeError> {
+        self.graph.restore(node)
+    }
+
+    pub fn record(&mut self, node: NodeIndex, kind: &str, value: String) -> Result<(), NodeError> {
+        self.graph.record(node, kind, value)
+    }
+
+    pub fn expire_at(&mut self, node: NodeIndex, at: Timestamp) -> Result<(), NodeError> {
+        self.graph.expire_at(node, at)
+    }
+
+    pub fn set_node_coalesce_window(
+        &mut self,
+        node: NodeIndex,
+        window: Option<Duration>,
+    ) -> Result<(), NodeError> {
+        self.graph.set_node_coalesce_window(node, window)
+    }
+
+    pub fn set_owner(
+        &mut self,
+        node: NodeIndex,
+        owner: Option<String>,
+        permissions: Permissions,
+    ) -> Result<(), NodeError> {
+        self.graph.set_owner(node, owner, permissions)
+    }
+
+    /// A [`Session`] on the shared graph, acting on behalf of this view's
+    /// principal.
+    pub fn session(&mut self) -> Session<'_> {
+        self.graph.session()
+    }
+}
+
+impl Deref for Scoped<'_> {
+    type Target = TerferGraph;
+
+    fn deref(&self) -> &TerferGraph {
+        self.graph
+    }
+}
+
+impl Drop for Scoped<'_> {
+    fn drop(&mut self) {
+        self.graph.principal = self.previous.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Permissions;
+    use crate::{Edge, Node, NodeError, Terfer, TerferGraph, TraversalOptions};
+
+    #[test]
+    fn mutations_are_checked() {
+        let mut tg = TerferGraph::new_tg_as("alice".to_string());
+        let private = tg.add_node(Node::new("1".to_string(), "Payroll".to_string()));
+        let shared = tg.add_node(Node::new("2".to_string(), "Handbook".to_string()));
+        tg.set_owner(shared, Some("alice".to_string()), Permissions::READ_ONLY)
+            .unwrap();
+        tg.set_alias(shared, "handbook").unwrap();
+
+        let mut bob = tg.as_principal("bob");
+        assert_eq!(
+            bob.update(shared, "edited".to_string()),
+            Err(NodeError::PermissionDenied(shared))
+        );
+        assert_eq!(
+            bob.delete(private),
+            Err(NodeError::PermissionDenied(private))
+        );
+        assert_eq!(
+            bob.set_owner(shared, Some("bob".to_string()), Permissions::PUBLIC),
+            Err(NodeError::PermissionDenied(shared))
+        );
+
+        let notes = bob.add_node(Node::new("3".to_string(), "Notes".to_string()));
+        bob.set_alias(notes, "notes").unwrap();
+        bob.add_edge(notes, shared, Edge::new("1".to_string()))
+            .unwrap();
+        assert_eq!(
+            bob.add_edge(shared, notes, Edge::new("2".to_string())),
+            Err(NodeError::PermissionDenied(shared))
+        );
+        assert_eq!(
+            bob.session()
+                .add_edge(shared, notes, Edge::new("2".to_string())),
+            Err(NodeError::PermissionDenied(shared))
+        );
+        assert_eq!(
+            bob.connect_by_alias("handbook", "notes", Edge::new("2".to_string())),
+            Err(NodeError::PermissionDenied(shared))
+        );
+        assert_eq!(bob.edge_count(), 1);
+        drop(bob);
+
+        // Bob's changes were made on the shared graph, where Alice acts again.
+        assert_eq!(tg.graph[notes].owner(), Some("bob"));
+        assert!(tg.node(notes).is_none());
+        assert_eq!(tg.edge_count(), 1);
+        tg.update(shared, "edited".to_string()).unwrap();
+        tg.delete(private).unwrap();
+    }
+
+    #[test]
+    fn unowned_nodes_cannot_be_claimed() {
+        let mut tg = TerferGraph::new_tg();
+        let legacy = tg.add_node(Node::new("1".to_string(), "Legacy".to_string()));
+
+        let mut mallory = tg.as_principal("mallory");
+        mallory.update(legacy, "edited".to_string()).unwrap();
+        assert_eq!(
+            mallory.set_owner(legacy, Some("mallory".to_string()), Permissions::PRIVATE),
+            Err(NodeError::PermissionDenied(legacy))
+        );
+        drop(mallory);
+
+        tg.set_owner(legacy, Some("alice".to_string()), Permissions::READ_ONLY)
+            .unwrap();
+        assert_eq!(tg.node(legacy).unwrap().owner(), Some("alice"));
+    }
+
+    #[test]
+    fn queries_skip_unreadable_nodes() {
+        let mut tg = TerferGraph::new_tg();
+        let root = tg.add_node(Node::new("1".to_string(), "Root".to_string()));
+        let secret = tg.add_node(Node::new("2".to_string(), "Secret".to_string()));
+        tg.set_owner(secret, Some("alice".to_string()), Permissions::PRIVATE)
+            .unwrap();
+        tg.set_alias(secret, "secret").unwrap();
+        tg.add_edge(root, secret, Edge::new("1".to_string()))
+            .unwrap();
+
+        let bob = tg.as_principal("bob");
+        assert!(bob.node(secret).is_none());
+        assert!(bob.node_by_alias("secret").is_none());
+        assert_eq!(bob.traverse(root, TraversalOptions::default()).count(), 1);
+        assert_eq!(bob.to_petgraph().graph.node_count(), 1);
+        drop(bob);
+
+        let alice = tg.as_principal("alice");
+        assert!(alice.node(secret).is_some());
+        assert_eq!(alice.traverse(root, TraversalOptions::default()).count(), 2);
+        drop(alice);
+        assert!(tg.node(secret).is_some());
+    }
+}
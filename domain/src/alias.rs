@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use petgraph::graph::NodeIndex;
+
+use crate::{hash, NodeError, TerferGraph};
+
+pub(crate) fn set_alias(
+    graph: &mut TerferGraph,
+    node: NodeIndex,
+    alias: &str,
+) -> Result<(), NodeError> {
+    if graph.graph.node_weight(node).is_none() {
+        return Err(NodeError::NotFound(node));
+    }
+    match graph.aliases.get(alias) {
+        Some(&owner) if owner == node => return Ok(()),
+        Some(_) => return Err(NodeError::AliasTaken(alias.to_string())),
+        None => {}
+    }
+
+    let weight = graph.node_mut(node);
+    let previous = weight.alias.replace(alias.to_string());
+    weight.alias_history.extend(previous.clone());
+    let aliases = Arc::make_mut(&mut graph.aliases);
+    if let Some(previous) = previous {
+        aliases.remove(&previous);
+    }
+    aliases.insert(alias.to_string(), node);
+    hash::touch(graph, node);
+    Ok(())
+}
+
+/// Puts `node` back to an earlier alias state, as captured by its alias and
+/// the length of its history at the time.
+pub(crate) fn restore(
+    graph: &mut TerferGraph,
+    node: NodeIndex,
+    alias: Option<String>,
+    history_len: usize,
+) {
+    let weight = graph.node_mut(node);
+    let current = weight.alias.take();
+    weight.alias_history.truncate(history_len);
+    weight.alias.clone_from(&alias);
+    let aliases = Arc::make_mut(&mut graph.aliases);
+    if let Some(current) = current {
+        aliases.remove(&current);
+    }
+    if let Some(alias) = alias {
+        aliases.insert(alias, node);
+    }
+    hash::touch(graph, node);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Edge, Node, NodeError, Terfer, TerferGraph};
+
+    #[test]
+    fn rename_tracks_history() {
+        let mut tg = TerferGraph::new_tg();
+        let node = tg.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+
+        tg.set_alias(node, "billing").unwrap();
+        tg.set_alias(node, "billing-root").unwrap();
+
+        assert_eq!(tg.node_by_alias("billing"), None);
+        assert_eq!(tg.node_by_alias("billing-root"), Some(node));
+        let node = tg.node(node).unwrap();
+        assert_eq!(node.alias(), Some("billing-root"));
+        assert_eq!(node.alias_history(), ["billing"]);
+    }
+
+    #[test]
+    fn aliases_are_unique() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+        let b = tg.add_node(Node::new("2".to_string(), "Node 2".to_string()));
+
+        tg.set_alias(a, "root").unwrap();
+        assert_eq!(
+            tg.set_alias(b, "root"),
+            Err(NodeError::AliasTaken("root".to_string()))
+        );
+    }
+
+    #[test]
+    fn connect_by_alias() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+        let b = tg.add_node(Node::new("2".to_string(), "Node 2".to_string()));
+        tg.set_alias(a, "billing-root").unwrap();
+        tg.set_alias(b, "invoices").unwrap();
+
+        tg.connect_by_alias("billing-root", "invoices", Edge::new("1".to_string()))
+            .unwrap();
+        assert_eq!(tg.edge_count(), 1);
+        assert_eq!(
+            tg.connect_by_alias("billing-root", "missing", Edge::new("2".to_string())),
+            Err(NodeError::UnknownAlias("missing".to_string()))
+        );
+
+        tg.delete(b).unwrap();
+        assert_eq!(tg.node_by_alias("invoices"), None);
+        assert_eq!(
+            tg.connect_by_alias("billing-root", "invoices", Edge::new("3".to_string())),
+            Err(NodeError::Deleted(b))
+        );
+        assert_eq!(tg.edge_count(), 1);
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use jiff::Timestamp;
+use petgraph::graph::NodeIndex;
+
+use crate::{hash, history, NodeError, TerferGraph};
+
+/// Something that happened to a node without a direct call on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The node reached its expiry and was soft-deleted.
+    Expired { node: NodeIndex, at: Timestamp },
+}
+
+pub(crate) fn expire_at(
+    graph: &mut TerferGraph,
+    node: NodeIndex,
+    at: Timestamp,
+) -> Result<(), NodeError> {
+    graph
+        .graph
+        .node_weight(node)
+        .ok_or(NodeError::NotFound(node))?;
+    let weight = graph.node_mut(node);
+    let previous = weight.expires_at.replace(at);
+    // A deleted node is scheduled again if it is restored.
+    if !weight.is_deleted() {
+        let expirations = Arc::make_mut(&mut graph.expirations);
+        if let Some(previous) = previous {
+            expirations.remove(&(previous, node));
+        }
+        expirations.insert((at, node));
+    }
+    hash::touch(graph, node);
+    Ok(())
+}
+
+pub(crate) fn process_expirations(graph: &mut TerferGraph, now: Timestamp) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    while let Some(&(at, node)) = graph.expirations.first() {
+        if at > now {
+            break;
+        }
+        Arc::make_mut(&mut graph.expirations).pop_first();
+        let weight = graph.node_mut(node);
+        weight.expires_at = None;
+        history::soft_delete(weight, now);
+        hash::touch(graph, node);
+        events.push(Event::Expired { node, at });
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::{Timestamp, ToSpan};
+    use petgraph::graph::NodeIndex;
+
+    use super::Event;
+    use crate::{Edge, Node, Terfer, TerferGraph, TraversalOptions};
+
+    #[test]
+    fn expired_nodes_are_soft_deleted() {
+        let now = Timestamp::from_second(1_700_000_000).unwrap();
+        let mut tg = TerferGraph::new_tg();
+        let root = tg.add_node(Node::new("1".to_string(), "Sprint".to_string()));
+        let early = tg.add_node(Node::new("2".to_string(), "Early task".to_string()));
+        let late = tg.add_node(Node::new("3".to_string(), "Late task".to_string()));
+        tg.add_edge(root, early, Edge::new("1".to_string()))
+            .unwrap();
+        tg.add_edge(root, late, Edge::new("2".to_string())).unwrap();
+
+        tg.expire_at(late, now + 2.hours()).unwrap();
+        tg.expire_at(early, now + 3.hours()).unwrap();
+        tg.expire_at(early, now + 1.hour()).unwrap();
+
+        assert!(tg.process_expirations(now).is_empty());
+        let events = tg.process_expirations(now + 90.minutes());
+        assert_eq!(
+            events,
+            [Event::Expired {
+                node: early,
+                at: now + 1.hour()
+            }]
+        );
+        assert_eq!(
+            tg.node(early).unwrap().deleted_at(),
+            Some(now + 90.minutes())
+        );
+        assert!(tg.process_expirations(now + 90.minutes()).is_empty());
+
+        let reachable = tg.traverse(root, TraversalOptions::default()).count();
+        assert_eq!(reachable, 2);
+    }
+
+    #[test]
+    fn missing_node_is_rejected() {
+        let mut tg = TerferGraph::new_tg();
+        assert!(tg
+            .expire_at(NodeIndex::new(0), Timestamp::UNIX_EPOCH)
+            .is_err());
+    }
+}
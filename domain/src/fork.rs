@@ -0,0 +1,295 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use petgraph::graph::{EdgeIndex, NodeIndex};
+
+use crate::{hash, GraphId, Node, NodeError, TerferGraph};
+
+/// What a fork needs to know about its parent. Nodes are only copied here
+/// when the fork first changes them, so making a fork costs no more than
+/// cloning the parent's node and edge lists.
+pub(crate) struct Parent {
+    graph: GraphId,
+    node_count: usize,
+    edge_count: usize,
+    originals: HashMap<NodeIndex, Arc<Node>>,
+}
+
+impl Parent {
+    /// Keeps `node` as the parent's version of `index` unless the fork has
+    /// changed it before or added it itself.
+    pub(crate) fn preserve(&mut self, index: NodeIndex, node: &Arc<Node>) {
+        if index.index() < self.node_count {
+            self.originals
+                .entry(index)
+                .or_insert_with(|| Arc::clone(node));
+        }
+    }
+}
+
+/// Changes made in a fork, see [`Terfer::diff_against_parent`](crate::Terfer::diff_against_parent).
+/// Indices below the parent's node and edge counts refer to the same items in
+/// both graphs.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ForkDiff {
+    pub added_nodes: Vec<NodeIndex>,
+    pub changed_nodes: Vec<NodeIndex>,
+    pub added_edges: Vec<EdgeIndex>,
+}
+
+impl ForkDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_nodes.is_empty() && self.changed_nodes.is_empty() && self.added_edges.is_empty()
+    }
+}
+
+pub(crate) fn fork(graph: &TerferGraph) -> TerferGraph {
+    TerferGraph {
+        id: GraphId::default(),
+        graph: graph.graph.clone(),
+        aliases: Arc::clone(&graph.aliases),
+        expirations: Arc::clone(&graph.expirations),
+        coalesce_window: graph.coalesce_window,
+        principal: graph.principal.clone(),
+        hashes: hash::clone_cache(graph),
+        parent: Some(Parent {
+            graph: graph.id,
+            node_count: graph.graph.node_count(),
+            edge_count: graph.graph.edge_count(),
+            originals: HashMap::new(),
+        }),
+    }
+}
+
+pub(crate) fn diff(graph: &TerferGraph, parent: &Parent) -> ForkDiff {
+    let mut diff = ForkDiff::default();
+
+    for index in graph.graph.node_indices() {
+        if index.index() >= parent.node_count {
+            diff.added_nodes.push(index);
+            continue;
+        }
+        // Pointer equality settles the common case; a node that was copied
+        // and then put back, e.g. by a rolled back session, compares equal.
+        if let Some(original) = parent.originals.get(&index) {
+            let current = &graph.graph[index];
+            if !Arc::ptr_eq(original, current) && original != current {
+                diff.changed_nodes.push(index);
+            }
+        }
+    }
+    diff.added_edges = graph.graph.edge_indices().skip(parent.edge_count).collect();
+
+    diff
+}
+
+pub(crate) fn apply(graph: &mut TerferGraph, fork: TerferGraph) -> Result<ForkDiff, NodeError> {
+    let parent = fork
+        .parent
+        .as_ref()
+        .filter(|parent| parent.graph == graph.id)
+        .ok_or(NodeError::NotAFork)?;
+    let diff = diff(&fork, parent);
+    check(graph, &fork, parent, &diff)?;
+
+    let mut applied = ForkDiff {
+        changed_nodes: diff.changed_nodes.clone(),
+        ..ForkDiff::default()
+    };
+    for &index in &diff.changed_nodes {
+        graph.replace_node(index, Arc::clone(&fork.graph[index]));
+        hash::touch(graph, index);
+    }
+
+    let mut added = HashMap::with_capacity(diff.added_nodes.len());
+    for &index in &diff.added_nodes {
+        let new_index = graph.insert_node(Arc::clone(&fork.graph[index]));
+        hash::touch(graph, new_index);
+        added.insert(index, new_index);
+        applied.added_nodes.push(new_index);
+    }
+
+    let local = |index: NodeIndex| added.get(&index).copied().unwrap_or(index);
+    for &edge in &diff.added_edges {
+        let (a, b) = fork
+            .graph
+            .edge_endpoints(edge)
+            .expect("edge index from diff");
+        let (a, b) = (local(a), local(b));
+        applied
+            .added_edges
+            .push(graph.graph.add_edge(a, b, fork.graph[edge].clone()));
+        hash::touch(graph, a);
+    }
+
+    Ok(applied)
+}
+
+/// Makes sure `diff` applies cleanly, so that [`apply`] either applies all of
+/// it or nothing.
+fn check(
+    graph: &TerferGraph,
+    fork: &TerferGraph,
+    parent: &Parent,
+    diff: &ForkDiff,
+) -> Result<(), NodeError> {
+    for &index in &diff.changed_nodes {
+        let current = graph
+            .graph
+            .node_weight(index)
+            .ok_or(NodeError::NotFound(index))?;
+        let original = &parent.originals[&index];
+        if !Arc::ptr_eq(original, current) && original != current {
+            return Err(NodeError::Conflict(index));
+        }
+    }
+    for &edge in &diff.added_edges {
+        let (a, b) = fork
+            .graph
+            .edge_endpoints(edge)
+            .expect("edge index from diff");
+        for index in [a, b] {
+            if index.index() < parent.node_count && graph.graph.node_weight(index).is_none() {
+                return Err(NodeError::NotFound(index));
+            }
+        }
+    }
+
+    // An alias may move to a node the fork changed or added as long as the
+    // node holding it in this graph is one the fork changed too, and gave it
+    // up there.
+    let changed: HashSet<_> = diff.changed_nodes.iter().copied().collect();
+    for &index in diff.changed_nodes.iter().chain(&diff.added_nodes) {
+        let Some(alias) = &fork.graph[index].alias else {
+            continue;
+        };
+        match graph.aliases.get(alias) {
+            Some(&holder) if holder == index && changed.contains(&index) => {}
+            Some(&holder)
+                if changed.contains(&holder)
+                    && fork.graph[holder].alias.as_ref() != Some(alias) => {}
+            Some(_) => return Err(NodeError::AliasTaken(alias.clone())),
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use petgraph::graph::NodeIndex;
+
+    use crate::{Edge, Node, NodeError, Terfer, TerferGraph};
+
+    #[test]
+    fn fork_shares_until_changed() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+        let b = tg.add_node(Node::new("2".to_string(), "Node 2".to_string()));
+
+        let mut fork = tg.fork();
+        assert!(Arc::ptr_eq(&tg.graph[a], &fork.graph[a]));
+        assert!(fork.diff_against_parent().unwrap().is_empty());
+
+        fork.set_alias(b, "renamed").unwrap();
+        let c = fork.add_node(Node::new("3".to_string(), "Node 3".to_string()));
+        fork.add_edge(a, c, Edge::new("1".to_string())).unwrap();
+
+        assert!(Arc::ptr_eq(&tg.graph[a], &fork.graph[a]));
+        assert_eq!(tg.node(b).unwrap().alias(), None);
+        let diff = fork.diff_against_parent().unwrap();
+        assert_eq!(diff.changed_nodes, [b]);
+        assert_eq!(diff.added_nodes, [c]);
+        assert_eq!(diff.added_edges.len(), 1);
+    }
+
+    #[test]
+    fn rolled_back_changes_are_not_reported() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+
+        let mut fork = tg.fork();
+        fork.session().set_alias(a, "discarded").unwrap();
+
+        assert!(fork.diff_against_parent().unwrap().is_empty());
+        assert!(tg.diff_against_parent().is_none());
+    }
+
+    #[test]
+    fn apply_keeps_changes_on_both_sides() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+        let b = tg.add_node(Node::new("2".to_string(), "Node 2".to_string()));
+        tg.set_alias(b, "invoices").unwrap();
+
+        let mut fork = tg.fork();
+        fork.update(a, "edited".to_string()).unwrap();
+        fork.set_alias(b, "bills").unwrap();
+        let c = fork.add_node(Node::new("3".to_string(), "Node 3".to_string()));
+        fork.set_alias(c, "invoices").unwrap();
+        fork.add_edge(a, c, Edge::new("1".to_string())).unwrap();
+        let d = tg.add_node(Node::new("4".to_string(), "Node 4".to_string()));
+
+        let applied = tg.apply_fork(fork).unwrap();
+        assert_eq!(applied.changed_nodes, [a, b]);
+        assert_eq!(applied.added_nodes, [NodeIndex::new(3)]);
+        let c = applied.added_nodes[0];
+        assert_eq!(tg.node(a).unwrap().value(), "edited");
+        assert_eq!(tg.node_by_alias("bills"), Some(b));
+        assert_eq!(tg.node_by_alias("invoices"), Some(c));
+        assert_eq!(tg.node(d).unwrap().value(), "Node 4");
+        assert_eq!(tg.graph.neighbors(a).collect::<Vec<_>>(), [c]);
+    }
+
+    #[test]
+    fn apply_rejects_conflicts() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+
+        let mut fork = tg.fork();
+        fork.update(a, "fork".to_string()).unwrap();
+        tg.update(a, "parent".to_string()).unwrap();
+        assert_eq!(tg.apply_fork(fork), Err(NodeError::Conflict(a)));
+        assert_eq!(tg.node(a).unwrap().value(), "parent");
+
+        let mut fork = tg.fork();
+        let b = fork.add_node(Node::new("2".to_string(), "Node 2".to_string()));
+        fork.set_alias(b, "root").unwrap();
+        tg.set_alias(a, "root").unwrap();
+        assert_eq!(
+            tg.apply_fork(fork),
+            Err(NodeError::AliasTaken("root".to_string()))
+        );
+        assert_eq!(tg.node_count(), 1);
+    }
+
+    #[test]
+    fn apply_rejects_other_graphs() {
+        let mut tg = TerferGraph::new_tg();
+        tg.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+        let mut other = TerferGraph::new_tg();
+        other.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+
+        let mut fork = other.fork();
+        fork.add_edge(
+            NodeIndex::new(0),
+            NodeIndex::new(0),
+            Edge::new("1".to_string()),
+        )
+        .unwrap();
+        assert_eq!(tg.apply_fork(fork), Err(NodeError::NotAFork));
+        assert_eq!(
+            tg.apply_fork(TerferGraph::new_tg()),
+            Err(NodeError::NotAFork)
+        );
+
+        let grandchild = tg.fork().fork();
+        assert_eq!(tg.apply_fork(grandchild), Err(NodeError::NotAFork));
+        assert_eq!(tg.edge_count(), 0);
+    }
+}
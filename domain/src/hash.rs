@@ -0,0 +1,578 @@
+//! Content hashes for change detection. They use FNV-1a over explicitly
+//! encoded fields, so a hash stays the same across processes and builds, and
+//! combine nodes and edges by wrapping addition, so they do not depend on
+//! index order. That also lets the live hashes be kept as running sums: a
+//! change takes out what a node added before and puts in what it adds now.
+//!
+//! Hashes only cover the nodes the principal can read and the edges between
+//! them, so they change with nothing the principal cannot see. A graph and
+//! the NDJSON it exports for its principal hash the same once imported for
+//! that principal.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use jiff::Timestamp;
+use petgraph::{graph::NodeIndex, visit::EdgeRef, Direction, Graph};
+
+use crate::{acl, telemetry, Edge, InstanceType, Node, TerferGraph};
+
+/// What [`Terfer::content_hash`](crate::Terfer::content_hash) covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashScope {
+    /// Live nodes with their current values, aliases, owners, permissions and
+    /// expiries, and the edges between them.
+    Live,
+    /// Every node and edge, including soft-deleted ones, with everything an
+    /// NDJSON export keeps of them: every instance in each node's history,
+    /// its earlier aliases and its coalescing window as well.
+    History,
+}
+
+/// Live hashes, kept up to date as the graph changes rather than recounted.
+#[derive(Clone, Default)]
+pub(crate) struct Cache {
+    nodes: HashMap<NodeIndex, u64>,
+    /// Per principal, as each one sees different nodes.
+    views: HashMap<Option<String>, View>,
+}
+
+#[derive(Clone, Default)]
+struct View {
+    /// The term of every node counted in `content` or in a cached subtree.
+    terms: HashMap<NodeIndex, Term>,
+    content: Option<u64>,
+    subtrees: HashMap<NodeIndex, u64>,
+}
+
+/// What a node adds to the live hashes that cover it: its own hash and those
+/// of its edges to live nodes, or nothing if it is not live itself. `edges`
+/// counts the outgoing edges of a live node, so a change to them, and so to
+/// what the node reaches, can be told apart from a change to its content.
+#[derive(Clone, Copy, Default)]
+struct Term {
+    hash: u64,
+    live: bool,
+    edges: usize,
+}
+
+struct Fnv(u64);
+
+impl Fnv {
+    fn new(tag: u8) -> Self {
+        let mut fnv = Fnv(0xcbf2_9ce4_8422_2325);
+        fnv.write(&[tag]);
+        fnv
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn str(&mut self, value: &str) {
+        self.write(&(value.len() as u64).to_le_bytes());
+        self.write(value.as_bytes());
+    }
+
+    fn opt_str(&mut self, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                self.write(&[1]);
+                self.str(value);
+            }
+            None => self.write(&[0]),
+        }
+    }
+
+    fn opt_timestamp(&mut self, value: Option<Timestamp>) {
+        match value {
+            Some(value) => {
+                self.write(&[1]);
+                self.write(&value.as_nanosecond().to_le_bytes());
+            }
+            None => self.write(&[0]),
+        }
+    }
+}
+
+fn live_node_hash(node: &Node) -> u64 {
+    let mut fnv = Fnv::new(b'n');
+    fnv.str(&node.id);
+    fnv.str(&node.name);
+    fnv.opt_str(node.alias.as_deref());
+    fnv.str(node.value());
+    fnv.opt_str(node.owner.as_deref());
+    fnv.write(&[
+        u8::from(node.permissions.read),
+        u8::from(node.permissions.write),
+    ]);
+    fnv.opt_timestamp(node.expires_at);
+    fnv.0
+}
+
+fn history_node_hash(node: &Node) -> u64 {
+    let mut fnv = Fnv::new(b'h');
+    fnv.write(&live_node_hash(node).to_le_bytes());
+    for alias in &node.alias_history {
+        fnv.str(alias);
+    }
+    fnv.opt_timestamp(node.deleted_at);
+    match node.coalesce_window {
+        Some(window) => {
+            fnv.write(&[1]);
+            fnv.write(&window.as_nanos().to_le_bytes());
+        }
+        None => fnv.write(&[0]),
+    }
+    for instance in &node.instances {
+        fnv.write(instance.id().as_bytes());
+        fnv.str(instance.value());
+        fnv.write(&instance.recorded_at().as_nanosecond().to_le_bytes());
+        match instance.instance_type() {
+            InstanceType::Created => fnv.write(&[0]),
+            InstanceType::Updated => fnv.write(&[1]),
+            InstanceType::Deleted => fnv.write(&[2]),
+            InstanceType::Restored => fnv.write(&[3]),
+            InstanceType::Custom(kind) => {
+                fnv.write(&[4]);
+                fnv.str(kind);
+            }
+        }
+    }
+    fnv.0
+}
+
+fn edge_hash(edge: &Edge, source: &Node, target: &Node) -> u64 {
+    let mut fnv = Fnv::new(b'e');
+    fnv.str(&edge.id);
+    fnv.str(&source.id);
+    fnv.str(&target.id);
+    fnv.0
+}
+
+fn lock(graph: &TerferGraph) -> MutexGuard<'_, Cache> {
+    // The cache only ever holds finished hashes, so a panic elsewhere cannot
+    // leave it half-written.
+    telemetry::lock("hashes", &graph.hashes).unwrap_or_else(|error| error.into_inner())
+}
+
+pub(crate) fn clone_cache(graph: &TerferGraph) -> Mutex<Cache> {
+    Mutex::new(lock(graph).clone())
+}
+
+/// Whether `node` counts towards the live hashes of `principal`.
+fn live(principal: Option<&str>, node: &Node) -> bool {
+    !node.is_deleted() && acl::readable_by(principal, node)
+}
+
+/// The term of `index` as it is now, or `None` if there is no such node.
+fn node_term(
+    graph: &Graph<Arc<Node>, Edge>,
+    principal: Option<&str>,
+    nodes: &mut HashMap<NodeIndex, u64>,
+    index: NodeIndex,
+) -> Option<Term> {
+    let node = graph.node_weight(index)?;
+    if !live(principal, node) {
+        return Some(Term::default());
+    }
+
+    let mut term = Term {
+        hash: *nodes.entry(index).or_insert_with(|| live_node_hash(node)),
+        live: true,
+        edges: 0,
+    };
+    for edge in graph.edges(index) {
+        term.edges += 1;
+        let target = &graph[edge.target()];
+        if live(principal, target) {
+            term.hash = term
+                .hash
+                .wrapping_add(edge_hash(edge.weight(), node, target));
+        }
+    }
+    Some(term)
+}
+
+/// `index` and every node with a path to it that only passes through nodes
+/// accepted by `through`.
+fn ancestors(
+    graph: &Graph<Arc<Node>, Edge>,
+    index: NodeIndex,
+    through: impl Fn(&Node) -> bool,
+) -> HashSet<NodeIndex> {
+    let mut seen = HashSet::from([index]);
+    let mut queue = VecDeque::from([index]);
+    while let Some(index) = queue.pop_front() {
+        for ancestor in graph.neighbors_directed(index, Direction::Incoming) {
+            if through(&graph[ancestor]) && seen.insert(ancestor) {
+                queue.push_back(ancestor);
+            }
+        }
+    }
+    seen
+}
+
+impl View {
+    fn term(
+        &mut self,
+        graph: &Graph<Arc<Node>, Edge>,
+        principal: Option<&str>,
+        nodes: &mut HashMap<NodeIndex, u64>,
+        index: NodeIndex,
+    ) -> Term {
+        *self.terms.entry(index).or_insert_with(|| {
+            node_term(graph, principal, nodes, index).expect("index of an existing node")
+        })
+    }
+
+    /// Swaps the old term of `index` for its new one in every hash that
+    /// counts it. Only a change to what the node reaches, or to whether it
+    /// is live, drops the subtrees that contain it.
+    fn update(
+        &mut self,
+        graph: &Graph<Arc<Node>, Edge>,
+        principal: Option<&str>,
+        nodes: &mut HashMap<NodeIndex, u64>,
+        index: NodeIndex,
+    ) {
+        let before = self.terms.remove(&index).unwrap_or_default();
+        let after = node_term(graph, principal, nodes, index);
+        if let Some(after) = after {
+            self.terms.insert(index, after);
+        }
+        let after = after.unwrap_or_default();
+        let delta = after.hash.wrapping_sub(before.hash);
+
+        let mut content_delta = delta;
+        if before.live != after.live {
+            // Edges into the node only count while it is live.
+            let sources: HashSet<_> = graph
+                .neighbors_directed(index, Direction::Incoming)
+                .collect();
+            for source in sources {
+                if let Some(old) = self.terms.get(&source).copied() {
+                    let new = node_term(graph, principal, nodes, source)
+                        .expect("source of an existing edge");
+                    content_delta = content_delta.wrapping_add(new.hash.wrapping_sub(old.hash));
+                    self.terms.insert(source, new);
+                }
+            }
+        }
+        if let Some(content) = &mut self.content {
+            *content = content.wrapping_add(content_delta);
+        }
+
+        if self.subtrees.is_empty() {
+            return;
+        }
+        if before.live != after.live || before.edges != after.edges {
+            for ancestor in ancestors(graph, index, |_| true) {
+                self.subtrees.remove(&ancestor);
+            }
+        } else if delta != 0 {
+            for ancestor in ancestors(graph, index, |node| live(principal, node)) {
+                if let Some(hash) = self.subtrees.get_mut(&ancestor) {
+                    *hash = hash.wrapping_add(delta);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn content_hash(graph: &TerferGraph, scope: HashScope) -> u64 {
+    if scope == HashScope::History {
+        let nodes = graph
+            .graph
+            .node_weights()
+            .filter(|node| acl::can_read(graph, node))
+            .map(|node| history_node_hash(node));
+        let edges = graph.graph.edge_references().filter_map(|edge| {
+            let (source, target) = (&graph.graph[edge.source()], &graph.graph[edge.target()]);
+            (acl::can_read(graph, source) && acl::can_read(graph, target))
+                .then(|| edge_hash(edge.weight(), source, target))
+        });
+        return nodes.chain(edges).fold(0, u64::wrapping_add);
+    }
+
+    let mut cache = lock(graph);
+    let Cache { nodes, views } = &mut *cache;
+    let view = views.entry(graph.principal.clone()).or_default();
+    if let Some(hash) = view.content {
+        return hash;
+    }
+
+    let principal = graph.principal.as_deref();
+    let hash = graph.graph.node_indices().fold(0u64, |hash, index| {
+        hash.wrapping_add(view.term(&graph.graph, principal, nodes, index).hash)
+    });
+    view.content = Some(hash);
+    hash
+}
+
+/// Hash of the live nodes reachable from `root` along outgoing edges, and the
+/// edges between them, or `None` if the principal cannot see `root`.
+pub(crate) fn subtree_hash(graph: &TerferGraph, root: NodeIndex) -> Option<u64> {
+    let principal = graph.principal.as_deref();
+    if !live(principal, graph.graph.node_weight(root)?) {
+        return None;
+    }
+
+    let mut cache = lock(graph);
+    let Cache { nodes, views } = &mut *cache;
+    let view = views.entry(graph.principal.clone()).or_default();
+    if let Some(&hash) = view.subtrees.get(&root) {
+        return Some(hash);
+    }
+
+    let mut hash = 0u64;
+    let mut seen = HashSet::from([root]);
+    let mut queue = VecDeque::from([root]);
+    while let Some(index) = queue.pop_front() {
+        hash = hash.wrapping_add(view.term(&graph.graph, principal, nodes, index).hash);
+        for target in graph.graph.neighbors(index) {
+            if live(principal, &graph.graph[target]) && seen.insert(target) {
+                queue.push_back(target);
+            }
+        }
+    }
+
+    view.subtrees.insert(root, hash);
+    Some(hash)
+}
+
+/// Records that `index`, or the edges leaving it, changed, after the fact. A
+/// node that was removed counts as changed too.
+pub(crate) fn touch(graph: &mut TerferGraph, index: NodeIndex) {
+    let cache = graph
+        .hashes
+        .get_mut()
+        .unwrap_or_else(|error| error.into_inner());
+    cache.nodes.remove(&index);
+    for (principal, view) in &mut cache.views {
+        view.update(&graph.graph, principal.as_deref(), &mut cache.nodes, index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use jiff::Timestamp;
+    use petgraph::graph::NodeIndex;
+
+    use super::{lock, Cache, HashScope};
+    use crate::{Edge, Node, Permissions, Terfer, TerferGraph};
+
+    fn node(id: &str) -> Node {
+        Node::new(id.to_string(), format!("Node {id}"))
+    }
+
+    #[test]
+    fn content_hash_tracks_live_content() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(node("a"));
+        let b = tg.add_node(node("b"));
+        tg.add_edge(a, b, Edge::new("1".to_string())).unwrap();
+        let before = tg.content_hash(HashScope::Live);
+        assert_eq!(tg.content_hash(HashScope::Live), before);
+
+        let mut buffer = Vec::new();
+        tg.export_ndjson(&mut buffer, |_| {}).unwrap();
+        let copy = TerferGraph::import_ndjson(buffer.as_slice(), |_| {}).unwrap();
+        assert_eq!(copy.content_hash(HashScope::Live), before);
+        assert_eq!(
+            copy.content_hash(HashScope::History),
+            tg.content_hash(HashScope::History)
+        );
+
+        tg.update(b, "changed".to_string()).unwrap();
+        let changed = tg.content_hash(HashScope::Live);
+        assert_ne!(changed, before);
+
+        tg.record(b, "approved", "changed".to_string()).unwrap();
+        assert_eq!(tg.content_hash(HashScope::Live), changed);
+        let history = tg.content_hash(HashScope::History);
+        tg.record(b, "audited", "changed".to_string()).unwrap();
+        assert_ne!(tg.content_hash(HashScope::History), history);
+
+        let (live, history) = (
+            tg.content_hash(HashScope::Live),
+            tg.content_hash(HashScope::History),
+        );
+        tg.set_owner(b, Some("alice".to_string()), Permissions::READ_ONLY)
+            .unwrap();
+        assert_ne!(tg.content_hash(HashScope::Live), live);
+        assert_ne!(tg.content_hash(HashScope::History), history);
+
+        let live = tg.content_hash(HashScope::Live);
+        tg.expire_at(a, Timestamp::UNIX_EPOCH).unwrap();
+        assert_ne!(tg.content_hash(HashScope::Live), live);
+
+        let history = tg.content_hash(HashScope::History);
+        tg.set_node_coalesce_window(a, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_ne!(tg.content_hash(HashScope::History), history);
+    }
+
+    #[test]
+    fn connecting_by_alias_changes_hashes() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(node("a"));
+        let b = tg.add_node(node("b"));
+        tg.set_alias(a, "a").unwrap();
+        tg.set_alias(b, "b").unwrap();
+        let content = tg.content_hash(HashScope::Live);
+        let subtree = tg.subtree_hash(a).unwrap();
+
+        tg.connect_by_alias("a", "b", Edge::new("1".to_string()))
+            .unwrap();
+        assert_ne!(tg.content_hash(HashScope::Live), content);
+        assert_ne!(tg.subtree_hash(a).unwrap(), subtree);
+
+        let mut buffer = Vec::new();
+        tg.export_ndjson(&mut buffer, |_| {}).unwrap();
+        let copy = TerferGraph::import_ndjson(buffer.as_slice(), |_| {}).unwrap();
+        assert_eq!(
+            copy.content_hash(HashScope::Live),
+            tg.content_hash(HashScope::Live)
+        );
+        assert_eq!(copy.subtree_hash(a), tg.subtree_hash(a));
+    }
+
+    #[test]
+    fn hashes_cover_what_the_principal_can_read() {
+        let mut tg = TerferGraph::new_tg();
+        let root = tg.add_node(node("root"));
+        let secret = tg.add_node(node("secret"));
+        tg.set_owner(secret, Some("alice".to_string()), Permissions::PRIVATE)
+            .unwrap();
+        tg.add_edge(root, secret, Edge::new("1".to_string()))
+            .unwrap();
+        let mut alone = TerferGraph::new_tg();
+        let alone_root = alone.add_node(node("root"));
+
+        let bob = tg.as_principal("bob");
+        let (live, history) = (
+            bob.content_hash(HashScope::Live),
+            bob.content_hash(HashScope::History),
+        );
+        assert_eq!(live, alone.content_hash(HashScope::Live));
+        assert_eq!(bob.subtree_hash(root), alone.subtree_hash(alone_root));
+        assert_eq!(bob.subtree_hash(secret), None);
+
+        let mut buffer = Vec::new();
+        bob.export_ndjson(&mut buffer, |_| {}).unwrap();
+        drop(bob);
+        let copy =
+            TerferGraph::import_ndjson_as(buffer.as_slice(), "bob".to_string(), |_| {}).unwrap();
+        assert_eq!(copy.content_hash(HashScope::Live), live);
+        assert_eq!(copy.content_hash(HashScope::History), history);
+
+        assert_ne!(tg.content_hash(HashScope::Live), live);
+        tg.update(secret, "changed".to_string()).unwrap();
+        let bob = tg.as_principal("bob");
+        assert_eq!(bob.content_hash(HashScope::Live), live);
+        assert_eq!(bob.content_hash(HashScope::History), history);
+    }
+
+    #[test]
+    fn subtree_hashes_are_updated_selectively() {
+        // billing -> invoices, search -> index
+        let mut tg = TerferGraph::new_tg();
+        let billing = tg.add_node(node("billing"));
+        let invoices = tg.add_node(node("invoices"));
+        let search = tg.add_node(node("search"));
+        let index = tg.add_node(node("index"));
+        tg.add_edge(billing, invoices, Edge::new("1".to_string()))
+            .unwrap();
+        tg.add_edge(search, index, Edge::new("2".to_string()))
+            .unwrap();
+
+        let billing_hash = tg.subtree_hash(billing).unwrap();
+        let search_hash = tg.subtree_hash(search).unwrap();
+        assert_eq!(tg.subtree_hash(invoices), tg.subtree_hash(invoices));
+
+        // A new value is swapped into the subtrees that contain the node.
+        tg.update(invoices, "changed".to_string()).unwrap();
+        let updated = {
+            let cache = lock(&tg);
+            let subtrees = &cache.views[&None].subtrees;
+            assert_eq!(subtrees.get(&search), Some(&search_hash));
+            subtrees[&billing]
+        };
+        assert_ne!(updated, billing_hash);
+
+        // Deleting it changes what they reach, so they are counted again.
+        tg.delete(invoices).unwrap();
+        {
+            let cache = lock(&tg);
+            let subtrees = &cache.views[&None].subtrees;
+            assert!(!subtrees.contains_key(&billing));
+            assert!(!subtrees.contains_key(&invoices));
+            assert_eq!(subtrees.get(&search), Some(&search_hash));
+        }
+        assert_eq!(tg.subtree_hash(invoices), None);
+        let mut alone = TerferGraph::new_tg();
+        let root = alone.add_node(node("billing"));
+        assert_eq!(tg.subtree_hash(billing), alone.subtree_hash(root));
+    }
+
+    #[test]
+    fn running_hashes_match_a_recount() {
+        fn hashes(tg: &TerferGraph, roots: &[NodeIndex]) -> Vec<Option<u64>> {
+            let mut hashes = vec![Some(tg.content_hash(HashScope::Live))];
+            hashes.extend(roots.iter().map(|&root| tg.subtree_hash(root)));
+            hashes
+        }
+
+        /// Checks the hashes kept since the last call, for the graph's own
+        /// principal and for Bob, against counting them from scratch.
+        fn check(tg: &mut TerferGraph, roots: &[NodeIndex]) {
+            assert!(lock(tg).views.values().all(|view| view.content.is_some()));
+            let kept = (hashes(tg, roots), hashes(&tg.as_principal("bob"), roots));
+            *lock(tg) = Cache::default();
+            let counted = (hashes(tg, roots), hashes(&tg.as_principal("bob"), roots));
+            assert_eq!(kept, counted);
+        }
+
+        // a -> b -> c <- d
+        let mut tg = TerferGraph::new_tg();
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(|id| tg.add_node(node(id)));
+        tg.add_edge(a, b, Edge::new("1".to_string())).unwrap();
+        tg.add_edge(b, c, Edge::new("2".to_string())).unwrap();
+        tg.add_edge(d, c, Edge::new("3".to_string())).unwrap();
+        let roots = [a, b, c, d];
+        check(&mut tg, &roots);
+
+        tg.update(c, "changed".to_string()).unwrap();
+        check(&mut tg, &roots);
+        tg.add_edge(c, a, Edge::new("4".to_string())).unwrap();
+        check(&mut tg, &roots);
+        tg.delete(b).unwrap();
+        check(&mut tg, &roots);
+        tg.restore(b).unwrap();
+        check(&mut tg, &roots);
+        tg.set_owner(c, Some("alice".to_string()), Permissions::PRIVATE)
+            .unwrap();
+        check(&mut tg, &roots);
+        tg.set_alias(d, "d").unwrap();
+        check(&mut tg, &roots);
+        {
+            let mut session = tg.session();
+            let e = session.add_node(node("e"));
+            session.add_edge(e, a, Edge::new("5".to_string())).unwrap();
+            session.add_edge(d, e, Edge::new("6".to_string())).unwrap();
+            session.update(a, "discarded".to_string()).unwrap();
+        }
+        check(&mut tg, &roots);
+        tg.expire_at(d, Timestamp::UNIX_EPOCH).unwrap();
+        tg.process_expirations(Timestamp::now());
+        check(&mut tg, &roots);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{sync::Arc, time::Duration};
+
+use jiff::Timestamp;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{hash, Node, NodeError, TerferGraph};
+
+/// What produced an [`Instance`]. The built-in kinds match the
+/// `instance_type` column of `node_instance`; `Custom` carries an
+/// application-defined name.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstanceType {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+    Custom(String),
+}
+
+/// One entry in a node's history. The value is the node's value as of this
+/// instance; it is shared rather than copied by instances that keep it, like
+/// deletes and restores, and by readers through [`Instance::value_ref`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instance {
+    id: Uuid,
+    value: Arc<str>,
+    instance_type: InstanceType,
+    recorded_at: Timestamp,
+}
+
+impl Instance {
+    pub(crate) fn new(
+        value: impl Into<Arc<str>>,
+        instance_type: InstanceType,
+        recorded_at: Timestamp,
+    ) -> Self {
+        Instance {
+            id: Uuid::new_v4(),
+            value: value.into(),
+            instance_type,
+            recorded_at,
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn value_ref(&self) -> Arc<str> {
+        Arc::clone(&self.value)
+    }
+
+    pub fn instance_type(&self) -> &InstanceType {
+        &self.instance_type
+    }
+
+    /// When the instance was recorded. An update that later updates were
+    /// folded into keeps the time of the first one, the start of its
+    /// coalescing window.
+    pub fn recorded_at(&self) -> Timestamp {
+        self.recorded_at
+    }
+}
+
+/// Marks a node deleted and records it, leaving its value as it was.
+pub(crate) fn soft_delete(node: &mut Node, at: Timestamp) {
+    let value = node.value_ref();
+    node.deleted_at = Some(at);
+    node.instances
+        .push(Instance::new(value, InstanceType::Deleted, at));
+}
+
+fn live_mut(graph: &mut TerferGraph, index: NodeIndex) -> Result<&mut Node, NodeError> {
+    let node = graph
+        .graph
+        .node_weight(index)
+        .ok_or(NodeError::NotFound(index))?;
+    if node.is_deleted() {
+        return Err(NodeError::Deleted(index));
+    }
+    Ok(graph.node_mut(index))
+}
+
+/// Adds an `Updated` instance, or folds `value` into the last one if that is
+/// an update recorded less than the node's coalescing window ago. Folding
+/// replaces the value, so the latest write is never lost, but keeps the
+/// timestamp, so the window does not slide with each write.
+pub(crate) fn update(
+    graph: &mut TerferGraph,
+    node: NodeIndex,
+    value: String,
+) -> Result<(), NodeError> {
+    let default_window = graph.coalesce_window;
+    let weight = live_mut(graph, node)?;
+    let now = Timestamp::now();
+
+    if let (Some(window), Some(last)) = (
+        weight.coalesce_window.or(default_window),
+        weight.instances.last_mut(),
+    ) {
+        let elapsed = now.as_nanosecond() - last.recorded_at.as_nanosecond();
+        let window = i128::try_from(window.as_nanos()).unwrap_or(i128::MAX);
+        if last.instance_type == InstanceType::Updated && elapsed < window {
+            last.value = value.into();
+            hash::touch(graph, node);
+            return Ok(());
+        }
+    }
+
+    weight
+        .instances
+        .push(Instance::new(value, InstanceType::Updated, now));
+    hash::touch(graph, node);
+    Ok(())
+}
+
+pub(crate) fn set_coalesce_window(
+    graph: &mut TerferGraph,
+    node: NodeIndex,
+    window: Option<Duration>,
+) -> Result<(), NodeError> {
+    graph
+        .graph
+        .node_weight(node)
+        .ok_or(NodeError::NotFound(node))?;
+    graph.node_mut(node).coalesce_window = window;
+    Ok(())
+}
+
+pub(crate) fn record(
+    graph: &mut TerferGraph,
+    node: NodeIndex,
+    kind: &str,
+    value: String,
+) -> Result<(), NodeError> {
+    live_mut(graph, node)?.instances.push(Instance::new(
+        value,
+        InstanceType::Custom(kind.to_string()),
+        Timestamp::now(),
+    ));
+    hash::touch(graph, node);
+    Ok(())
+}
+
+pub(crate) fn delete(graph: &mut TerferGraph, node: NodeIndex) -> Result<(), NodeError> {
+    let weight = live_mut(graph, node)?;
+    soft_delete(weight, Timestamp::now());
+    if let Some(at) = weight.expires_at {
+        Arc::make_mut(&mut graph.expirations).remove(&(at, node));
+    }
+    hash::touch(graph, node);
+    Ok(())
+}
+
+pub(crate) fn restore(graph: &mut TerferGraph, node: NodeIndex) -> Result<(), NodeError> {
+    let weight = graph
+        .graph
+        .node_weight(node)
+        .ok_or(NodeError::NotFound(node))?;
+    if !weight.is_deleted() {
+        return Err(NodeError::NotDeleted(node));
+    }
+
+    let weight = graph.node_mut(node);
+    let value = weight.value_ref();
+    weight.deleted_at = None;
+    weight.instances.push(Instance::new(
+        value,
+        InstanceType::Restored,
+        Timestamp::now(),
+    ));
+    // An expiry set while the node was deleted, or cut short by a manual
+    // delete, applies again now that it is live.
+    if let Some(at) = weight.expires_at {
+        Arc::make_mut(&mut graph.expirations).insert((at, node));
+    }
+    hash::touch(graph, node);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::InstanceType;
+    use crate::{Node, NodeError, Terfer, TerferGraph};
+
+    #[test]
+    fn history_distinguishes_instance_types() {
+        let mut tg = TerferGraph::new_tg();
+        let node = tg.add_node(Node::new("1".to_string(), "draft".to_string()));
+
+        tg.update(node, "final".to_string()).unwrap();
+        tg.record(node, "approved", "final".to_string()).unwrap();
+        tg.delete(node).unwrap();
+        tg.restore(node).unwrap();
+
+        let node = tg.node(node).unwrap();
+        let types: Vec<_> = node
+            .history()
+            .iter()
+            .map(|instance| instance.instance_type().clone())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                InstanceType::Created,
+                InstanceType::Updated,
+                InstanceType::Custom("approved".to_string()),
+                InstanceType::Deleted,
+                InstanceType::Restored,
+            ]
+        );
+        assert_eq!(node.value(), "final");
+        assert_eq!(node.name(), "draft");
+    }
+
+    #[test]
+    fn deleted_nodes_reject_changes() {
+        let mut tg = TerferGraph::new_tg();
+        let node = tg.add_node(Node::new("1".to_string(), "draft".to_string()));
+
+        assert_eq!(tg.restore(node), Err(NodeError::NotDeleted(node)));
+        tg.delete(node).unwrap();
+        assert_eq!(
+            tg.update(node, "x".to_string()),
+            Err(NodeError::Deleted(node))
+        );
+        assert_eq!(
+            tg.record(node, "approved", "x".to_string()),
+            Err(NodeError::Deleted(node))
+        );
+        assert_eq!(tg.delete(node), Err(NodeError::Deleted(node)));
+    }
+
+    #[test]
+    fn updates_within_window_are_coalesced() {
+        let mut tg = TerferGraph::new_tg();
+        tg.set_coalesce_window(Some(Duration::from_secs(3600)));
+        let hot = tg.add_node(Node::new("1".to_string(), "0".to_string()));
+        let cold = tg.add_node(Node::new("2".to_string(), "0".to_string()));
+        tg.set_node_coalesce_window(cold, Some(Duration::ZERO))
+            .unwrap();
+
+        for value in 1..=50 {
+            tg.update(hot, value.to_string()).unwrap();
+            tg.update(cold, value.to_string()).unwrap();
+        }
+        tg.record(hot, "approved", "50".to_string()).unwrap();
+        tg.update(hot, "51".to_string()).unwrap();
+
+        let hot = tg.node(hot).unwrap();
+        assert_eq!(hot.history().len(), 4);
+        assert_eq!(hot.history()[1].value(), "50");
+        assert_eq!(hot.value(), "51");
+        assert_eq!(tg.node(cold).unwrap().history().len(), 51);
+    }
+}
@@ -0,0 +1,105 @@
+use petgraph::{graph::NodeIndex, visit::EdgeRef, Graph};
+
+use crate::{acl, TerferGraph};
+
+/// A node's current state, detached from its history.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeSummary {
+    pub id: String,
+    pub alias: Option<String>,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EdgeSummary {
+    pub id: String,
+}
+
+/// A plain petgraph copy of the live, readable part of a [`TerferGraph`], for
+/// running petgraph's algorithms. Indices in `graph` are its own; translate
+/// them with [`PetgraphExport::domain_node`] before passing them back.
+pub struct PetgraphExport {
+    pub graph: Graph<NodeSummary, EdgeSummary>,
+    domain_nodes: Vec<NodeIndex>,
+}
+
+impl PetgraphExport {
+    /// The domain node `index` was exported from, or `None` if `index` is not
+    /// a node of this export.
+    pub fn domain_node(&self, index: NodeIndex) -> Option<NodeIndex> {
+        self.domain_nodes.get(index.index()).copied()
+    }
+}
+
+pub(crate) fn to_petgraph(graph: &TerferGraph) -> PetgraphExport {
+    let mut export = Graph::with_capacity(graph.graph.node_count(), graph.graph.edge_count());
+    let mut domain_nodes = Vec::with_capacity(graph.graph.node_count());
+    let mut exported = vec![None; graph.graph.node_count()];
+
+    for index in graph.graph.node_indices() {
+        let node = &graph.graph[index];
+        if node.is_deleted() || !acl::can_read(graph, node) {
+            continue;
+        }
+        exported[index.index()] = Some(export.add_node(NodeSummary {
+            id: node.id.clone(),
+            alias: node.alias.clone(),
+            value: node.value().to_string(),
+        }));
+        domain_nodes.push(index);
+    }
+
+    for edge in graph.graph.edge_references() {
+        if let (Some(a), Some(b)) = (
+            exported[edge.source().index()],
+            exported[edge.target().index()],
+        ) {
+            export.add_edge(
+                a,
+                b,
+                EdgeSummary {
+                    id: edge.weight().id.to_string(),
+                },
+            );
+        }
+    }
+
+    PetgraphExport {
+        graph: export,
+        domain_nodes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::{algo::toposort, graph::NodeIndex};
+
+    use crate::{Edge, Node, Terfer, TerferGraph};
+
+    #[test]
+    fn algorithm_results_map_back() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(Node::new("a".to_string(), "A".to_string()));
+        let deleted = tg.add_node(Node::new("x".to_string(), "X".to_string()));
+        let b = tg.add_node(Node::new("b".to_string(), "B".to_string()));
+        let c = tg.add_node(Node::new("c".to_string(), "C".to_string()));
+        tg.add_edge(b, c, Edge::new("1".to_string())).unwrap();
+        tg.add_edge(a, b, Edge::new("2".to_string())).unwrap();
+        tg.add_edge(a, deleted, Edge::new("3".to_string())).unwrap();
+        tg.delete(deleted).unwrap();
+        tg.update(c, "C2".to_string()).unwrap();
+
+        let export = tg.to_petgraph();
+        assert_eq!(export.graph.node_count(), 3);
+        assert_eq!(export.graph.edge_count(), 2);
+
+        let order: Vec<_> = toposort(&export.graph, None)
+            .unwrap()
+            .into_iter()
+            .map(|index| export.domain_node(index))
+            .collect();
+        assert_eq!(order, [Some(a), Some(b), Some(c)]);
+        assert_eq!(export.domain_node(NodeIndex::new(3)), None);
+        assert_eq!(export.graph[NodeIndex::new(2)].value, "C2");
+    }
+}
@@ -1,43 +1,639 @@
-use petgraph::{graph::NodeIndex, Graph};
+mod acl;
+mod alias;
+mod expiry;
+mod fork;
+mod hash;
+mod history;
+mod interop;
+mod ndjson;
+mod partition;
+mod session;
+mod telemetry;
+mod traversal;
 
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    io::{self, BufRead, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use jiff::Timestamp;
+use petgraph::{
+    graph::{EdgeIndex, NodeIndex},
+    Graph,
+};
+
+pub use acl::{Permissions, Scoped};
+pub use expiry::Event;
+pub use fork::ForkDiff;
+pub use hash::HashScope;
+pub use history::{Instance, InstanceType};
+pub use interop::{EdgeSummary, NodeSummary, PetgraphExport};
+pub use partition::Subgraph;
+pub use session::Session;
+pub use traversal::{Limit, Step, Traversal, TraversalOptions};
+
+#[derive(Clone, PartialEq, Eq)]
 pub struct Node {
     id: String,
     name: String,
+    alias: Option<String>,
+    alias_history: Vec<String>,
+    expires_at: Option<Timestamp>,
+    deleted_at: Option<Timestamp>,
+    instances: Vec<Instance>,
+    coalesce_window: Option<Duration>,
+    owner: Option<String>,
+    permissions: Permissions,
 }
 
+impl Node {
+    pub fn new(id: String, name: String) -> Self {
+        let created = Instance::new(name.as_str(), InstanceType::Created, Timestamp::now());
+        Node {
+            id,
+            name,
+            alias: None,
+            alias_history: Vec::new(),
+            expires_at: None,
+            deleted_at: None,
+            instances: vec![created],
+            coalesce_window: None,
+            owner: None,
+            permissions: Permissions::default(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The name the node was created with. Its current value starts out the
+    /// same and moves on with each instance.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        self.instances
+            .last()
+            .map_or(self.name.as_str(), |instance| instance.value())
+    }
+
+    /// The current value as a shared string, for holding on to it without
+    /// borrowing the graph or copying the value.
+    pub fn value_ref(&self) -> Arc<str> {
+        self.instances
+            .last()
+            .map_or_else(|| Arc::from(self.name.as_str()), Instance::value_ref)
+    }
+
+    /// Every instance of this node, oldest first.
+    pub fn history(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn alias(&self) -> Option<&str> {
+        self.alias.as_deref()
+    }
+
+    /// Aliases this node has been renamed away from, oldest first.
+    pub fn alias_history(&self) -> &[String] {
+        &self.alias_history
+    }
+
+    pub fn expires_at(&self) -> Option<Timestamp> {
+        self.expires_at
+    }
+
+    pub fn deleted_at(&self) -> Option<Timestamp> {
+        self.deleted_at
+    }
+
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        self.permissions
+    }
+
+    /// Soft-deleted nodes stay in the graph for their history but are skipped
+    /// by traversals.
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+/// Cheap to clone, so forks can copy the edge list as it is.
+#[derive(Clone)]
 pub struct Edge {
-    id: String,
+    id: Arc<str>,
 }
 
-pub type TerferGraph = Graph<Node, Edge>;
+impl Edge {
+    pub fn new(id: String) -> Self {
+        Edge { id: id.into() }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NodeError {
+    NotFound(NodeIndex),
+    AliasTaken(String),
+    UnknownAlias(String),
+    Deleted(NodeIndex),
+    NotDeleted(NodeIndex),
+    PermissionDenied(NodeIndex),
+    /// The node was changed both in a fork and in its parent.
+    Conflict(NodeIndex),
+    /// The graph being applied is not a fork of the one it is applied to.
+    NotAFork,
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeError::NotFound(index) => write!(f, "node {} does not exist", index.index()),
+            NodeError::AliasTaken(alias) => write!(f, "alias {alias} is already in use"),
+            NodeError::UnknownAlias(alias) => write!(f, "no node has alias {alias}"),
+            NodeError::Deleted(index) => write!(f, "node {} is deleted", index.index()),
+            NodeError::NotDeleted(index) => write!(f, "node {} is not deleted", index.index()),
+            NodeError::PermissionDenied(index) => {
+                write!(f, "permission denied on node {}", index.index())
+            }
+            NodeError::Conflict(index) => {
+                write!(f, "node {} changed in both fork and parent", index.index())
+            }
+            NodeError::NotAFork => write!(f, "graph is not a fork of this graph"),
+        }
+    }
+}
+
+impl std::error::Error for NodeError {}
+
+/// Tells graphs apart; every graph gets a new one when it is made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct GraphId(u64);
+
+impl Default for GraphId {
+    fn default() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        GraphId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Nodes and lookups sit behind an `Arc` so a [fork](Terfer::fork) can share
+/// them with its parent until one side changes them.
+#[derive(Default)]
+pub struct TerferGraph {
+    id: GraphId,
+    graph: Graph<Arc<Node>, Edge>,
+    aliases: Arc<HashMap<String, NodeIndex>>,
+    /// Pending expiries of live nodes, soonest first.
+    expirations: Arc<BTreeSet<(Timestamp, NodeIndex)>>,
+    /// Used for nodes that have no window of their own.
+    coalesce_window: Option<Duration>,
+    /// Who mutations and queries are made on behalf of, fixed when the graph
+    /// is made. `None` bypasses permission checks.
+    principal: Option<String>,
+    hashes: Mutex<hash::Cache>,
+    parent: Option<fork::Parent>,
+}
+
+impl TerferGraph {
+    /// Adds a node that may already carry an alias or expiry, keeping the
+    /// lookups that point at it in step.
+    fn insert_node(&mut self, node: Arc<Node>) -> NodeIndex {
+        let index = self.graph.add_node(node);
+        self.index_node(index);
+        index
+    }
+
+    /// Swaps in another version of the node at `index`, moving its alias and
+    /// expiry along with it.
+    fn replace_node(&mut self, index: NodeIndex, node: Arc<Node>) {
+        let previous = &self.graph[index];
+        if let Some(alias) = &previous.alias {
+            // Another node may have taken the alias over already.
+            if self.aliases.get(alias) == Some(&index) {
+                Arc::make_mut(&mut self.aliases).remove(alias);
+            }
+        }
+        if let Some(at) = previous.expires_at {
+            Arc::make_mut(&mut self.expirations).remove(&(at, index));
+        }
+        if let Some(parent) = &mut self.parent {
+            parent.preserve(index, &self.graph[index]);
+        }
+        self.graph[index] = node;
+        self.index_node(index);
+    }
+
+    /// Adds an edge on behalf of the principal, who needs write access to
+    /// its source.
+    fn connect(&mut self, a: NodeIndex, b: NodeIndex, edge: Edge) -> Result<EdgeIndex, NodeError> {
+        acl::check_write(self, a)?;
+        if self.graph.node_weight(b).is_none() {
+            return Err(NodeError::NotFound(b));
+        }
+        let index = self.graph.add_edge(a, b, edge);
+        hash::touch(self, a);
+        Ok(index)
+    }
+
+    /// Resolves two aliases the principal can see and connects their nodes.
+    fn connect_aliases(&mut self, a: &str, b: &str, edge: Edge) -> Result<EdgeIndex, NodeError> {
+        let resolve = |alias: &str| {
+            let index = self
+                .aliases
+                .get(alias)
+                .copied()
+                .filter(|&index| acl::can_read(self, &self.graph[index]))
+                .ok_or_else(|| NodeError::UnknownAlias(alias.to_string()))?;
+            if self.graph[index].is_deleted() {
+                Err(NodeError::Deleted(index))
+            } else {
+                Ok(index)
+            }
+        };
+        let (a, b) = (resolve(a)?, resolve(b)?);
+        self.connect(a, b, edge)
+    }
+
+    fn index_node(&mut self, index: NodeIndex) {
+        let node = &self.graph[index];
+        if let Some(alias) = &node.alias {
+            Arc::make_mut(&mut self.aliases).insert(alias.clone(), index);
+        }
+        if let Some(at) = node.expires_at.filter(|_| !node.is_deleted()) {
+            Arc::make_mut(&mut self.expirations).insert((at, index));
+        }
+    }
+
+    /// Copies the node first if it is still shared with a fork or parent. In
+    /// a fork, the node as it was at the fork is kept for
+    /// [`Terfer::diff_against_parent`].
+    fn node_mut(&mut self, index: NodeIndex) -> &mut Node {
+        let node = &mut self.graph[index];
+        if let Some(parent) = &mut self.parent {
+            parent.preserve(index, node);
+        }
+        Arc::make_mut(node)
+    }
+}
 
 pub trait Terfer {
     fn new_tg() -> Self;
+    /// Makes an empty graph that acts on behalf of `principal` for as long as
+    /// it lives: nodes it creates are owned by it, changing a node or adding
+    /// an edge from it needs write permission, and [`Terfer::node`],
+    /// [`Terfer::node_by_alias`], traversals, [`Terfer::to_petgraph`] and
+    /// [`Terfer::export_ndjson`] leave out nodes it cannot read. Graphs made
+    /// with [`Terfer::new_tg`] skip these checks.
+    fn new_tg_as(principal: String) -> Self;
     fn add_node(&mut self, node: Node) -> NodeIndex;
-    fn add_edge(&mut self, a: NodeIndex, b: NodeIndex, edge: Edge);
+    fn add_edge(&mut self, a: NodeIndex, b: NodeIndex, edge: Edge) -> Result<(), NodeError>;
     fn node_count(&self) -> usize;
     fn edge_count(&self) -> usize;
+    fn session(&mut self) -> Session<'_>;
+    /// A view of this graph that acts on behalf of `principal` until it is
+    /// dropped, with the checks described under [`Terfer::new_tg_as`]. This is
+    /// how several principals share one graph.
+    fn as_principal(&mut self, principal: &str) -> Scoped<'_>;
+    /// Streams one JSON record per line, all nodes first and then all edges.
+    /// Nodes the principal cannot read, and edges to or from them, are left
+    /// out. `progress` is called with the number of records written so far.
+    fn export_ndjson<W: Write>(&self, writer: W, progress: impl FnMut(usize)) -> io::Result<()>;
+    /// Builds a graph from the output of [`Terfer::export_ndjson`] one line
+    /// at a time. `progress` is called with the number of records read so far.
+    fn import_ndjson<R: BufRead>(reader: R, progress: impl FnMut(usize)) -> io::Result<Self>
+    where
+        Self: Sized;
+    /// Like [`Terfer::import_ndjson`], but the graph acts on behalf of
+    /// `principal` as with [`Terfer::new_tg_as`].
+    fn import_ndjson_as<R: BufRead>(
+        reader: R,
+        principal: String,
+        progress: impl FnMut(usize),
+    ) -> io::Result<Self>
+    where
+        Self: Sized;
+    fn node(&self, index: NodeIndex) -> Option<&Node>;
+    /// The current value of `node` without copying it, see
+    /// [`Node::value_ref`].
+    fn value_ref(&self, node: NodeIndex) -> Option<Arc<str>>;
+    /// Runs `f` over the current value of `node` in place.
+    fn map_value<R>(&self, node: NodeIndex, f: impl FnOnce(&str) -> R) -> Option<R>;
+    /// Gives `node` a unique human-readable key. Any alias it had before is
+    /// released and kept in [`Node::alias_history`].
+    fn set_alias(&mut self, node: NodeIndex, alias: &str) -> Result<(), NodeError>;
+    /// The live node with `alias`. Soft-deleted nodes keep their alias, so it
+    /// stays taken, but are not returned.
+    fn node_by_alias(&self, alias: &str) -> Option<NodeIndex>;
+    /// Adds an edge between two aliased nodes. Fails with
+    /// [`NodeError::Deleted`] if either of them is soft-deleted.
+    fn connect_by_alias(&mut self, a: &str, b: &str, edge: Edge) -> Result<(), NodeError>;
+    /// Walks outgoing edges breadth-first from `start`. Hitting any bound in
+    /// `options` ends the walk with a [`Step::Truncated`] marker.
+    fn traverse(&self, start: NodeIndex, options: TraversalOptions) -> Traversal<'_>;
+    /// Makes a logical copy that shares every node with this graph until one
+    /// of the two changes it.
+    fn fork(&self) -> Self
+    where
+        Self: Sized;
+    /// What changed in a fork since it was made. `None` if this graph is not
+    /// a fork.
+    fn diff_against_parent(&self) -> Option<ForkDiff>;
+    /// Merges what changed in `fork` since it was made from this graph into
+    /// this graph, keeping whatever this graph changed in the meantime. Fails
+    /// with [`NodeError::NotAFork`] if `fork` was not made from this graph,
+    /// and without changing anything if both sides changed the same node or
+    /// an alias would be taken twice. The fork is used up, so it cannot be
+    /// applied twice. The returned diff uses this graph's indices.
+    fn apply_fork(&mut self, fork: Self) -> Result<ForkDiff, NodeError>
+    where
+        Self: Sized;
+    /// Splits the graph into its weakly connected components, each a graph of
+    /// its own with the same principal. Nodes the principal cannot read are
+    /// left out, and so are the edges through them.
+    fn components(&self) -> Vec<Subgraph<Self>>
+    where
+        Self: Sized;
+    /// Splits the graph into the nodes matching `predicate` and the rest.
+    /// Edges between the two halves are dropped. Nodes the principal cannot
+    /// read are not passed to `predicate` and end up in neither half.
+    fn partition(&self, predicate: impl FnMut(&Node) -> bool) -> (Subgraph<Self>, Subgraph<Self>)
+    where
+        Self: Sized;
+    /// Schedules `node` to be soft-deleted by the first call to
+    /// [`Terfer::process_expirations`] at or after `at`, replacing any
+    /// earlier schedule.
+    fn expire_at(&mut self, node: NodeIndex, at: Timestamp) -> Result<(), NodeError>;
+    /// Soft-deletes every node whose expiry is at or before `now`, in expiry
+    /// order, and reports each one.
+    fn process_expirations(&mut self, now: Timestamp) -> Vec<Event>;
+    /// Sets the node's value. Updates that land within the coalescing window
+    /// of the previous one are folded into it instead of growing the history.
+    fn update(&mut self, node: NodeIndex, value: String) -> Result<(), NodeError>;
+    /// Sets the coalescing window for nodes without one of their own. `None`,
+    /// the default, records every update.
+    fn set_coalesce_window(&mut self, window: Option<Duration>);
+    /// Overrides the graph's coalescing window for `node`. `None` falls back
+    /// to the graph's window; use `Duration::ZERO` to record every update.
+    fn set_node_coalesce_window(
+        &mut self,
+        node: NodeIndex,
+        window: Option<Duration>,
+    ) -> Result<(), NodeError>;
+    /// Soft-deletes `node`, see [`Node::is_deleted`].
+    fn delete(&mut self, node: NodeIndex) -> Result<(), NodeError>;
+    fn restore(&mut self, node: NodeIndex) -> Result<(), NodeError>;
+    /// Adds an application-defined instance to the history of `node`. It
+    /// becomes the node's value like any other instance.
+    fn record(&mut self, node: NodeIndex, kind: &str, value: String) -> Result<(), NodeError>;
+    /// Copies the live nodes and the edges between them into a plain petgraph
+    /// graph.
+    fn to_petgraph(&self) -> PetgraphExport;
+    /// Changes who owns `node` and what everyone else may do with it. Only
+    /// the current owner may do this; a node without an owner can only be
+    /// given one by a graph without a principal.
+    fn set_owner(
+        &mut self,
+        node: NodeIndex,
+        owner: Option<String>,
+        permissions: Permissions,
+    ) -> Result<(), NodeError>;
+    /// A hash that changes exactly when the content in `scope` that the
+    /// principal can read does. The live hash is counted once and then kept
+    /// up to date as the graph changes.
+    fn content_hash(&self, scope: HashScope) -> u64;
+    /// The live hash of everything reachable from `root`, or `None` if
+    /// `root` is missing, deleted or unreadable. Cached per root and kept up
+    /// to date as nodes change; only a change to edges, or to whether a node
+    /// is live, clears the subtrees that contain it.
+    fn subtree_hash(&self, root: NodeIndex) -> Option<u64>;
 }
 
 impl Terfer for TerferGraph {
     fn new_tg() -> Self {
-        TerferGraph::new()
+        TerferGraph::default()
     }
 
-    fn add_node(&mut self, node: Node) -> NodeIndex {
-        self.add_node(node)
+    fn new_tg_as(principal: String) -> Self {
+        TerferGraph {
+            principal: Some(principal),
+            ..TerferGraph::default()
+        }
     }
 
-    fn add_edge(&mut self, a: NodeIndex, b: NodeIndex, edge: Edge) {
-        self.add_edge(a, b, edge);
+    fn add_node(&mut self, mut node: Node) -> NodeIndex {
+        let _span = telemetry::span("add_node");
+        acl::claim(self, &mut node);
+        let index = self.graph.add_node(Arc::new(node));
+        hash::touch(self, index);
+        telemetry::mutated(self, "add_node");
+        index
+    }
+
+    fn add_edge(&mut self, a: NodeIndex, b: NodeIndex, edge: Edge) -> Result<(), NodeError> {
+        let _span = telemetry::span("connect");
+        let result = self.connect(a, b, edge).map(drop);
+        telemetry::outcome(self, "connect", result)
     }
 
     fn node_count(&self) -> usize {
-        self.node_count()
+        self.graph.node_count()
     }
 
     fn edge_count(&self) -> usize {
-        self.edge_count()
+        self.graph.edge_count()
+    }
+
+    fn session(&mut self) -> Session<'_> {
+        Session::new(self)
+    }
+
+    fn as_principal(&mut self, principal: &str) -> Scoped<'_> {
+        Scoped::new(self, principal)
+    }
+
+    fn export_ndjson<W: Write>(&self, writer: W, progress: impl FnMut(usize)) -> io::Result<()> {
+        ndjson::export(self, writer, progress)
+    }
+
+    fn import_ndjson<R: BufRead>(reader: R, progress: impl FnMut(usize)) -> io::Result<Self> {
+        let _span = telemetry::span("import");
+        let graph = ndjson::import(reader, None, progress);
+        match &graph {
+            Ok(graph) => telemetry::mutated(graph, "import"),
+            Err(error) => telemetry::failed("import", error),
+        }
+        graph
+    }
+
+    fn import_ndjson_as<R: BufRead>(
+        reader: R,
+        principal: String,
+        progress: impl FnMut(usize),
+    ) -> io::Result<Self> {
+        let _span = telemetry::span("import");
+        let graph = ndjson::import(reader, Some(principal), progress);
+        match &graph {
+            Ok(graph) => telemetry::mutated(graph, "import"),
+            Err(error) => telemetry::failed("import", error),
+        }
+        graph
+    }
+
+    fn node(&self, index: NodeIndex) -> Option<&Node> {
+        self.graph
+            .node_weight(index)
+            .map(|node| &**node)
+            .filter(|node| acl::can_read(self, node))
+    }
+
+    fn value_ref(&self, node: NodeIndex) -> Option<Arc<str>> {
+        self.node(node).map(Node::value_ref)
+    }
+
+    fn map_value<R>(&self, node: NodeIndex, f: impl FnOnce(&str) -> R) -> Option<R> {
+        self.node(node).map(|node| f(node.value()))
+    }
+
+    fn set_alias(&mut self, node: NodeIndex, alias: &str) -> Result<(), NodeError> {
+        let _span = telemetry::span("set_alias");
+        let result =
+            acl::check_write(self, node).and_then(|()| alias::set_alias(self, node, alias));
+        telemetry::outcome(self, "set_alias", result)
+    }
+
+    fn node_by_alias(&self, alias: &str) -> Option<NodeIndex> {
+        self.aliases.get(alias).copied().filter(|&index| {
+            let node = &self.graph[index];
+            !node.is_deleted() && acl::can_read(self, node)
+        })
+    }
+
+    fn connect_by_alias(&mut self, a: &str, b: &str, edge: Edge) -> Result<(), NodeError> {
+        let _span = telemetry::span("connect");
+        let result = self.connect_aliases(a, b, edge).map(drop);
+        telemetry::outcome(self, "connect", result)
+    }
+
+    fn traverse(&self, start: NodeIndex, options: TraversalOptions) -> Traversal<'_> {
+        Traversal::new(self, start, options)
+    }
+
+    fn fork(&self) -> Self {
+        fork::fork(self)
+    }
+
+    fn diff_against_parent(&self) -> Option<ForkDiff> {
+        self.parent.as_ref().map(|parent| fork::diff(self, parent))
+    }
+
+    fn apply_fork(&mut self, fork: Self) -> Result<ForkDiff, NodeError> {
+        let _span = telemetry::span("apply_fork");
+        let result = fork::apply(self, fork);
+        telemetry::outcome(self, "apply_fork", result)
+    }
+
+    fn components(&self) -> Vec<Subgraph> {
+        partition::components(self)
+    }
+
+    fn partition(&self, predicate: impl FnMut(&Node) -> bool) -> (Subgraph, Subgraph) {
+        partition::partition(self, predicate)
+    }
+
+    fn expire_at(&mut self, node: NodeIndex, at: Timestamp) -> Result<(), NodeError> {
+        let _span = telemetry::span("expire_at");
+        let result = acl::check_write(self, node).and_then(|()| expiry::expire_at(self, node, at));
+        telemetry::outcome(self, "expire_at", result)
+    }
+
+    fn process_expirations(&mut self, now: Timestamp) -> Vec<Event> {
+        let _span = telemetry::span("expire");
+        let events = expiry::process_expirations(self, now);
+        if !events.is_empty() {
+            telemetry::mutated(self, "expire");
+        }
+        events
+    }
+
+    fn update(&mut self, node: NodeIndex, value: String) -> Result<(), NodeError> {
+        let _span = telemetry::span("update");
+        let result = acl::check_write(self, node).and_then(|()| history::update(self, node, value));
+        telemetry::outcome(self, "update", result)
+    }
+
+    fn set_coalesce_window(&mut self, window: Option<Duration>) {
+        let _span = telemetry::span("set_coalesce_window");
+        self.coalesce_window = window;
+        telemetry::mutated(self, "set_coalesce_window");
+    }
+
+    fn set_node_coalesce_window(
+        &mut self,
+        node: NodeIndex,
+        window: Option<Duration>,
+    ) -> Result<(), NodeError> {
+        let _span = telemetry::span("set_coalesce_window");
+        let result = acl::check_write(self, node)
+            .and_then(|()| history::set_coalesce_window(self, node, window));
+        telemetry::outcome(self, "set_coalesce_window", result)
+    }
+
+    fn delete(&mut self, node: NodeIndex) -> Result<(), NodeError> {
+        let _span = telemetry::span("delete");
+        let result = acl::check_write(self, node).and_then(|()| history::delete(self, node));
+        telemetry::outcome(self, "delete", result)
+    }
+
+    fn restore(&mut self, node: NodeIndex) -> Result<(), NodeError> {
+        let _span = telemetry::span("restore");
+        let result = acl::check_write(self, node).and_then(|()| history::restore(self, node));
+        telemetry::outcome(self, "restore", result)
+    }
+
+    fn record(&mut self, node: NodeIndex, kind: &str, value: String) -> Result<(), NodeError> {
+        let _span = telemetry::span("record");
+        let result =
+            acl::check_write(self, node).and_then(|()| history::record(self, node, kind, value));
+        telemetry::outcome(self, "record", result)
+    }
+
+    fn to_petgraph(&self) -> PetgraphExport {
+        interop::to_petgraph(self)
+    }
+
+    fn set_owner(
+        &mut self,
+        node: NodeIndex,
+        owner: Option<String>,
+        permissions: Permissions,
+    ) -> Result<(), NodeError> {
+        let _span = telemetry::span("set_owner");
+        let result = acl::set_owner(self, node, owner, permissions);
+        telemetry::outcome(self, "set_owner", result)
+    }
+
+    fn content_hash(&self, scope: HashScope) -> u64 {
+        hash::content_hash(self, scope)
+    }
+
+    fn subtree_hash(&self, root: NodeIndex) -> Option<u64> {
+        hash::subtree_hash(self, root)
     }
 }
 
@@ -45,23 +641,31 @@ impl Terfer for TerferGraph {
 mod tests {
     use super::*;
 
+    #[test]
+    fn value_reads_share_the_instance() {
+        let mut tg = TerferGraph::new_tg();
+        let node = tg.add_node(Node::new("1".to_string(), "draft".to_string()));
+        tg.update(node, "x".repeat(1 << 16)).unwrap();
+
+        let value = tg.value_ref(node).unwrap();
+        let latest = tg.node(node).unwrap().history().last().unwrap().value();
+        assert!(std::ptr::eq(value.as_ptr(), latest.as_ptr()));
+
+        tg.delete(node).unwrap();
+        assert!(Arc::ptr_eq(&value, &tg.value_ref(node).unwrap()));
+        assert_eq!(tg.map_value(node, str::len), Some(1 << 16));
+        assert_eq!(tg.map_value(NodeIndex::new(1), str::len), None);
+    }
+
     #[test]
     fn test() {
         let mut tg = TerferGraph::new_tg();
-        let node1 = Node {
-            id: "1".to_string(),
-            name: "Node 1".to_string(),
-        };
-        let node2 = Node {
-            id: "2".to_string(),
-            name: "Node 2".to_string(),
-        };
+        let node1 = Node::new("1".to_string(), "Node 1".to_string());
+        let node2 = Node::new("2".to_string(), "Node 2".to_string());
         let node_index1 = tg.add_node(node1);
         let node_index2 = tg.add_node(node2);
-        let edge = Edge {
-            id: "1".to_string(),
-        };
-        tg.add_edge(node_index1, node_index2, edge);
+        let edge = Edge::new("1".to_string());
+        tg.add_edge(node_index1, node_index2, edge).unwrap();
         assert_eq!(tg.node_count(), 2);
         assert_eq!(tg.edge_count(), 1);
     }
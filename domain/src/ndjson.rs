@@ -0,0 +1,311 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, BufWriter, Write},
+    sync::Arc,
+    time::Duration,
+};
+
+use petgraph::visit::EdgeRef;
+use serde::{Deserialize, Serialize};
+
+use jiff::Timestamp;
+
+use crate::{acl, Edge, Instance, Node, NodeError, Permissions, TerferGraph};
+
+/// One line of an NDJSON export. Nodes are always written before edges so an
+/// import can resolve edge endpoints without holding the whole file. A node
+/// without instances is imported with an empty history, its value being its
+/// name.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record<'a> {
+    Node {
+        #[serde(borrow)]
+        id: Cow<'a, str>,
+        #[serde(borrow)]
+        name: Cow<'a, str>,
+        #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+        alias: Option<Cow<'a, str>>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        alias_history: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<Timestamp>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        deleted_at: Option<Timestamp>,
+        #[serde(default)]
+        instances: Cow<'a, [Instance]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        coalesce_window: Option<Duration>,
+        #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+        owner: Option<Cow<'a, str>>,
+        #[serde(default, skip_serializing_if = "is_default")]
+        permissions: Permissions,
+    },
+    Edge {
+        #[serde(borrow)]
+        id: Cow<'a, str>,
+        #[serde(borrow)]
+        source: Cow<'a, str>,
+        #[serde(borrow)]
+        target: Cow<'a, str>,
+    },
+}
+
+fn is_default(permissions: &Permissions) -> bool {
+    *permissions == Permissions::default()
+}
+
+pub(crate) fn export<W: Write>(
+    graph: &TerferGraph,
+    writer: W,
+    mut progress: impl FnMut(usize),
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    let mut written = 0;
+
+    for node in graph.graph.node_weights() {
+        if !acl::can_read(graph, node) {
+            continue;
+        }
+        let record = Record::Node {
+            id: Cow::Borrowed(&node.id),
+            name: Cow::Borrowed(&node.name),
+            alias: node.alias.as_deref().map(Cow::Borrowed),
+            alias_history: node.alias_history.clone(),
+            expires_at: node.expires_at,
+            deleted_at: node.deleted_at,
+            instances: Cow::Borrowed(&node.instances),
+            coalesce_window: node.coalesce_window,
+            owner: node.owner.as_deref().map(Cow::Borrowed),
+            permissions: node.permissions,
+        };
+        serde_json::to_writer(&mut writer, &record)?;
+        writer.write_all(b"\n")?;
+        written += 1;
+        progress(written);
+    }
+
+    for edge in graph.graph.edge_references() {
+        let (source, target) = (&graph.graph[edge.source()], &graph.graph[edge.target()]);
+        if !acl::can_read(graph, source) || !acl::can_read(graph, target) {
+            continue;
+        }
+        let record = Record::Edge {
+            id: Cow::Borrowed(&edge.weight().id),
+            source: Cow::Borrowed(&source.id),
+            target: Cow::Borrowed(&target.id),
+        };
+        serde_json::to_writer(&mut writer, &record)?;
+        writer.write_all(b"\n")?;
+        written += 1;
+        progress(written);
+    }
+
+    writer.flush()
+}
+
+pub(crate) fn import<R: BufRead>(
+    reader: R,
+    principal: Option<String>,
+    mut progress: impl FnMut(usize),
+) -> io::Result<TerferGraph> {
+    let mut graph = TerferGraph {
+        principal,
+        ..TerferGraph::default()
+    };
+    let mut indices = HashMap::new();
+    let mut edge_ids = HashSet::new();
+    let mut read = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(&line)? {
+            Record::Node {
+                id,
+                name,
+                alias,
+                alias_history,
+                expires_at,
+                deleted_at,
+                instances,
+                coalesce_window,
+                owner,
+                permissions,
+            } => {
+                if let Some(alias) = alias.as_deref() {
+                    if graph.aliases.contains_key(alias) {
+                        let error = NodeError::AliasTaken(alias.to_string());
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            error.to_string(),
+                        ));
+                    }
+                }
+
+                if indices.contains_key(id.as_ref()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("duplicate node id {id}"),
+                    ));
+                }
+
+                let id = id.into_owned();
+                // Built as it is in the file rather than through `Node::new`,
+                // so importing the same file twice gives the same history.
+                let node = Node {
+                    id: id.clone(),
+                    name: name.into_owned(),
+                    alias: alias.map(Cow::into_owned),
+                    alias_history,
+                    expires_at,
+                    deleted_at,
+                    instances: instances.into_owned(),
+                    coalesce_window,
+                    owner: owner.map(Cow::into_owned),
+                    permissions,
+                };
+                let index = graph.insert_node(Arc::new(node));
+                indices.insert(id, index);
+            }
+            Record::Edge { id, source, target } => {
+                let endpoint = |id: &str| {
+                    indices.get(id).copied().ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("edge references unknown node {id}"),
+                        )
+                    })
+                };
+                let (a, b) = (endpoint(&source)?, endpoint(&target)?);
+                if !edge_ids.insert(id.to_string()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("duplicate edge id {id}"),
+                    ));
+                }
+                graph.graph.add_edge(a, b, Edge::new(id.into_owned()));
+            }
+        }
+
+        read += 1;
+        progress(read);
+    }
+
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use jiff::Timestamp;
+    use petgraph::graph::NodeIndex;
+
+    use crate::{Edge, HashScope, Node, Permissions, Terfer, TerferGraph};
+
+    #[test]
+    fn round_trip() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(Node::new("1".to_string(), "Node \"1\"".to_string()));
+        let b = tg.add_node(Node::new("2".to_string(), "Node 2".to_string()));
+        tg.set_alias(a, "first").unwrap();
+        tg.set_alias(a, "root").unwrap();
+        tg.expire_at(b, Timestamp::UNIX_EPOCH).unwrap();
+        tg.record(b, "approved", "Node 2".to_string()).unwrap();
+        tg.set_node_coalesce_window(b, Some(Duration::from_secs(5)))
+            .unwrap();
+        tg.set_owner(b, Some("alice".to_string()), Permissions::READ_ONLY)
+            .unwrap();
+        tg.add_edge(a, b, Edge::new("1".to_string())).unwrap();
+
+        let mut buffer = Vec::new();
+        let mut exported = 0;
+        tg.export_ndjson(&mut buffer, |count| exported = count)
+            .unwrap();
+        assert_eq!(exported, 3);
+        assert_eq!(buffer.iter().filter(|&&byte| byte == b'\n').count(), 3);
+
+        let mut imported = 0;
+        let copy = TerferGraph::import_ndjson(buffer.as_slice(), |count| imported = count).unwrap();
+        assert_eq!(imported, 3);
+        assert_eq!(copy.node_count(), 2);
+        assert_eq!(copy.edge_count(), 1);
+        assert_eq!(copy.graph[a].name, "Node \"1\"");
+        assert_eq!(copy.node_by_alias("root"), Some(a));
+        assert_eq!(copy.graph[a].alias_history, ["first"]);
+        assert_eq!(copy.expirations.first(), Some(&(Timestamp::UNIX_EPOCH, b)));
+        assert_eq!(copy.graph[b].history(), tg.graph[b].history());
+        assert_eq!(copy.graph[b].coalesce_window, Some(Duration::from_secs(5)));
+        assert_eq!(copy.graph[b].owner(), Some("alice"));
+        assert_eq!(copy.graph[b].permissions(), Permissions::READ_ONLY);
+    }
+
+    #[test]
+    fn unreadable_nodes_are_not_exported() {
+        let mut tg = TerferGraph::new_tg();
+        let root = tg.add_node(Node::new("1".to_string(), "Root".to_string()));
+        let secret = tg.add_node(Node::new("2".to_string(), "Secret".to_string()));
+        tg.set_owner(secret, Some("alice".to_string()), Permissions::PRIVATE)
+            .unwrap();
+        tg.add_edge(root, secret, Edge::new("1".to_string()))
+            .unwrap();
+
+        let mut buffer = Vec::new();
+        tg.as_principal("bob")
+            .export_ndjson(&mut buffer, |_| {})
+            .unwrap();
+        let copy = TerferGraph::import_ndjson(buffer.as_slice(), |_| {}).unwrap();
+        assert_eq!((copy.node_count(), copy.edge_count()), (1, 0));
+        assert!(!String::from_utf8(buffer).unwrap().contains("Secret"));
+    }
+
+    #[test]
+    fn unknown_endpoint_is_rejected() {
+        let input = r#"{"type":"edge","id":"1","source":"1","target":"2"}"#;
+        let error = TerferGraph::import_ndjson(input.as_bytes(), |_| {})
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        for input in [
+            concat!(
+                r#"{"type":"node","id":"1","name":"Node 1"}"#,
+                "\n",
+                r#"{"type":"node","id":"1","name":"Node 1 again"}"#,
+            ),
+            concat!(
+                r#"{"type":"node","id":"1","name":"Node 1"}"#,
+                "\n",
+                r#"{"type":"edge","id":"1","source":"1","target":"1"}"#,
+                "\n",
+                r#"{"type":"edge","id":"1","source":"1","target":"1"}"#,
+            ),
+        ] {
+            let error = TerferGraph::import_ndjson(input.as_bytes(), |_| {})
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn nodes_without_instances_import_the_same_every_time() {
+        let input = r#"{"type":"node","id":"1","name":"Node 1"}"#;
+        let first = TerferGraph::import_ndjson(input.as_bytes(), |_| {}).unwrap();
+        let second = TerferGraph::import_ndjson(input.as_bytes(), |_| {}).unwrap();
+        assert!(first.graph[NodeIndex::new(0)].history().is_empty());
+        assert_eq!(first.node(NodeIndex::new(0)).unwrap().value(), "Node 1");
+        assert_eq!(
+            first.content_hash(HashScope::History),
+            second.content_hash(HashScope::History)
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use std::{collections::HashMap, sync::Arc};
+
+use petgraph::{graph::NodeIndex, unionfind::UnionFind, visit::EdgeRef};
+
+use crate::{acl, Node, TerferGraph};
+
+/// A graph extracted by [`Terfer::components`](crate::Terfer::components) or
+/// [`Terfer::partition`](crate::Terfer::partition). Indices in `graph` are its
+/// own; translate them with [`Subgraph::source_node`] to use them on the graph
+/// it was extracted from.
+pub struct Subgraph<G = TerferGraph> {
+    pub graph: G,
+    source_nodes: Vec<NodeIndex>,
+}
+
+impl<G> Subgraph<G> {
+    /// The node `index` was extracted from, or `None` if `index` is not a node
+    /// of this subgraph.
+    pub fn source_node(&self, index: NodeIndex) -> Option<NodeIndex> {
+        self.source_nodes.get(index.index()).copied()
+    }
+}
+
+/// Nodes the principal may see. Nothing else is handed to callers or copied
+/// into the extracted graphs.
+fn readable(graph: &TerferGraph) -> impl Iterator<Item = NodeIndex> + '_ {
+    graph
+        .graph
+        .node_indices()
+        .filter(|&index| acl::can_read(graph, &graph.graph[index]))
+}
+
+pub(crate) fn components(graph: &TerferGraph) -> Vec<Subgraph> {
+    let mut sets = UnionFind::new(graph.graph.node_count());
+    for edge in graph.graph.edge_references() {
+        let (source, target) = (&graph.graph[edge.source()], &graph.graph[edge.target()]);
+        if acl::can_read(graph, source) && acl::can_read(graph, target) {
+            sets.union(edge.source().index(), edge.target().index());
+        }
+    }
+
+    let mut groups: Vec<Vec<NodeIndex>> = Vec::new();
+    let mut group_of_root = HashMap::new();
+    for index in readable(graph) {
+        let group = *group_of_root
+            .entry(sets.find(index.index()))
+            .or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+        groups[group].push(index);
+    }
+
+    groups.iter().map(|nodes| extract(graph, nodes)).collect()
+}
+
+pub(crate) fn partition(
+    graph: &TerferGraph,
+    mut predicate: impl FnMut(&Node) -> bool,
+) -> (Subgraph, Subgraph) {
+    let (matching, rest): (Vec<_>, Vec<_>) =
+        readable(graph).partition(|&index| predicate(&graph.graph[index]));
+    (extract(graph, &matching), extract(graph, &rest))
+}
+
+/// Builds a standalone graph from `nodes` and the edges running between them.
+/// The nodes themselves are shared with `graph`, not copied.
+fn extract(graph: &TerferGraph, nodes: &[NodeIndex]) -> Subgraph {
+    let mut sub = TerferGraph {
+        coalesce_window: graph.coalesce_window,
+        principal: graph.principal.clone(),
+        ..TerferGraph::default()
+    };
+    let mut indices = HashMap::with_capacity(nodes.len());
+
+    for &index in nodes {
+        let new_index = sub.insert_node(Arc::clone(&graph.graph[index]));
+        indices.insert(index, new_index);
+    }
+
+    for &index in nodes {
+        for edge in graph.graph.edges(index) {
+            if let Some(&target) = indices.get(&edge.target()) {
+                sub.graph
+                    .add_edge(indices[&index], target, edge.weight().clone());
+            }
+        }
+    }
+
+    Subgraph {
+        graph: sub,
+        source_nodes: nodes.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::graph::NodeIndex;
+
+    use crate::{Edge, Node, Permissions, Terfer, TerferGraph};
+
+    fn graph() -> TerferGraph {
+        // billing: 1 -> 2, 2 -> 3; search: 4 -> 5; 3 -> 4 links the two teams.
+        let mut tg = TerferGraph::new_tg();
+        let nodes: Vec<_> = [
+            ("1", "billing"),
+            ("2", "billing"),
+            ("3", "billing"),
+            ("4", "search"),
+            ("5", "search"),
+        ]
+        .into_iter()
+        .map(|(id, name)| tg.add_node(Node::new(id.to_string(), name.to_string())))
+        .collect();
+        for (id, (a, b)) in [(0, 1), (1, 2), (3, 4), (2, 3)].into_iter().enumerate() {
+            tg.add_edge(nodes[a], nodes[b], Edge::new(id.to_string()))
+                .unwrap();
+        }
+        tg
+    }
+
+    #[test]
+    fn components_are_weakly_connected() {
+        let mut tg = graph();
+        tg.add_node(Node::new("6".to_string(), "lonely".to_string()));
+
+        let components = tg.components();
+        let sizes: Vec<_> = components
+            .iter()
+            .map(|component| (component.graph.node_count(), component.graph.edge_count()))
+            .collect();
+        assert_eq!(sizes, [(5, 4), (1, 0)]);
+        assert_eq!(
+            components[1].source_node(NodeIndex::new(0)),
+            Some(NodeIndex::new(5))
+        );
+        assert_eq!(components[1].source_node(NodeIndex::new(1)), None);
+    }
+
+    #[test]
+    fn partition_keeps_internal_edges() {
+        let mut tg = graph();
+        tg.set_alias(NodeIndex::new(0), "billing-root").unwrap();
+
+        let (billing, search) = tg.partition(|node| node.name() == "billing");
+
+        assert_eq!(
+            (billing.graph.node_count(), billing.graph.edge_count()),
+            (3, 2)
+        );
+        assert_eq!(
+            (search.graph.node_count(), search.graph.edge_count()),
+            (2, 1)
+        );
+        assert!(billing.graph.node_by_alias("billing-root").is_some());
+        assert_eq!(search.graph.components().len(), 1);
+        let search_nodes: Vec<_> = search
+            .graph
+            .graph
+            .node_indices()
+            .map(|index| search.source_node(index))
+            .collect();
+        assert_eq!(
+            search_nodes,
+            [Some(NodeIndex::new(3)), Some(NodeIndex::new(4))]
+        );
+    }
+
+    #[test]
+    fn unreadable_nodes_are_left_out() {
+        let mut tg = graph();
+        let secret = tg.add_node(Node::new("6".to_string(), "billing".to_string()));
+        tg.set_owner(secret, Some("alice".to_string()), Permissions::PRIVATE)
+            .unwrap();
+        tg.add_edge(NodeIndex::new(0), secret, Edge::new("5".to_string()))
+            .unwrap();
+        let bob = tg.as_principal("bob");
+
+        let components = bob.components();
+        assert_eq!(components.len(), 1);
+        assert_eq!(components[0].graph.node_count(), 5);
+
+        let mut seen = 0;
+        let (billing, _) = bob.partition(|node| {
+            seen += 1;
+            node.name() == "billing"
+        });
+        assert_eq!(seen, 5);
+        assert_eq!(billing.graph.node_count(), 3);
+        assert_eq!(billing.graph.principal.as_deref(), Some("bob"));
+    }
+}
@@ -0,0 +1,322 @@
+use std::{ops::Deref, sync::Arc, time::Duration};
+
+use jiff::Timestamp;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+
+use crate::{acl, alias, hash, telemetry, Edge, Node, NodeError, Permissions, Terfer, TerferGraph};
+
+enum Staged {
+    Node(NodeIndex),
+    Edge(EdgeIndex),
+    Alias {
+        node: NodeIndex,
+        previous: Option<String>,
+        history_len: usize,
+    },
+    /// The node as it was before a change to its value, history, expiry,
+    /// owner or coalescing window.
+    Content {
+        node: NodeIndex,
+        previous: Arc<Node>,
+    },
+}
+
+/// Mutation guard returned by [`Terfer::session`](crate::Terfer::session).
+///
+/// Changes are applied to the graph as they are made so indices can be used
+/// straight away, but unless [`Session::commit`] is called they are undone
+/// when the guard is dropped, including while unwinding from a panic.
+pub struct Session<'a> {
+    graph: &'a mut TerferGraph,
+    staged: Vec<Staged>,
+}
+
+impl<'a> Session<'a> {
+    pub(crate) fn new(graph: &'a mut TerferGraph) -> Self {
+        Session {
+            graph,
+            staged: Vec::new(),
+        }
+    }
+
+    pub fn add_node(&mut self, mut node: Node) -> NodeIndex {
+        let _span = telemetry::span("add_node");
+        acl::claim(self.graph, &mut node);
+        let index = self.graph.graph.add_node(Arc::new(node));
+        hash::touch(self.graph, index);
+        self.staged.push(Staged::Node(index));
+        telemetry::mutated(self.graph, "add_node");
+        index
+    }
+
+    pub fn add_edge(&mut self, a: NodeIndex, b: NodeIndex, edge: Edge) -> Result<(), NodeError> {
+        let _span = telemetry::span("connect");
+        let result = self.graph.connect(a, b, edge).map(|index| {
+            self.staged.push(Staged::Edge(index));
+        });
+        telemetry::outcome(self.graph, "connect", result)
+    }
+
+    pub fn connect_by_alias(&mut self, a: &str, b: &str, edge: Edge) -> Result<(), NodeError> {
+        let _span = telemetry::span("connect");
+        let result = self.graph.connect_aliases(a, b, edge).map(|index| {
+            self.staged.push(Staged::Edge(index));
+        });
+        telemetry::outcome(self.graph, "connect", result)
+    }
+
+    pub fn set_alias(&mut self, node: NodeIndex, alias: &str) -> Result<(), NodeError> {
+        let (previous, history_len) = match self.graph.graph.node_weight(node) {
+            Some(weight) => (weight.alias.clone(), weight.alias_history.len()),
+            None => return Err(NodeError::NotFound(node)),
+        };
+        self.graph.set_alias(node, alias)?;
+        self.staged.push(Staged::Alias {
+            node,
+            previous,
+            history_len,
+        });
+        Ok(())
+    }
+
+    pub fn update(&mut self, node: NodeIndex, value: String) -> Result<(), NodeError> {
+        self.stage_content(node, |graph| graph.update(node, value))
+    }
+
+    pub fn delete(&mut self, node: NodeIndex) -> Result<(), NodeError> {
+        self.stage_content(node, |graph| graph.delete(node))
+    }
+
+    pub fn restore(&mut self, node: NodeIndex) -> Result<(), NodeError> {
+        self.stage_content(node, |graph| graph.restore(node))
+    }
+
+    pub fn record(&mut self, node: NodeIndex, kind: &str, value: String) -> Result<(), NodeError> {
+        self.stage_content(node, |graph| graph.record(node, kind, value))
+    }
+
+    pub fn expire_at(&mut self, node: NodeIndex, at: Timestamp) -> Result<(), NodeError> {
+        self.stage_content(node, |graph| graph.expire_at(node, at))
+    }
+
+    pub fn set_node_coalesce_window(
+        &mut self,
+        node: NodeIndex,
+        window: Option<Duration>,
+    ) -> Result<(), NodeError> {
+        self.stage_content(node, |graph| graph.set_node_coalesce_window(node, window))
+    }
+
+    pub fn set_owner(
+        &mut self,
+        node: NodeIndex,
+        owner: Option<String>,
+        permissions: Permissions,
+    ) -> Result<(), NodeError> {
+        self.stage_content(node, |graph| graph.set_owner(node, owner, permissions))
+    }
+
+    fn stage_content(
+        &mut self,
+        node: NodeIndex,
+        change: impl FnOnce(&mut TerferGraph) -> Result<(), NodeError>,
+    ) -> Result<(), NodeError> {
+        let previous = match self.graph.graph.node_weight(node) {
+            Some(weight) => Arc::clone(weight),
+            None => return Err(NodeError::NotFound(node)),
+        };
+        change(self.graph)?;
+        self.staged.push(Staged::Content { node, previous });
+        Ok(())
+    }
+
+    /// Keeps every change made through this session.
+    pub fn commit(mut self) {
+        self.staged.clear();
+        telemetry::mutated(self.graph, "commit");
+    }
+}
+
+impl Deref for Session<'_> {
+    type Target = TerferGraph;
+
+    fn deref(&self) -> &TerferGraph {
+        self.graph
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        if self.staged.is_empty() {
+            return;
+        }
+
+        let _span = telemetry::span("rollback");
+        // Undo in reverse so every removal hits the last index, which petgraph
+        // removes without shifting anything that existed before the session.
+        for staged in self.staged.drain(..).rev() {
+            match staged {
+                Staged::Node(index) => {
+                    alias::restore(self.graph, index, None, 0);
+                    self.graph.graph.remove_node(index);
+                    hash::touch(self.graph, index);
+                }
+                Staged::Edge(index) => {
+                    if let Some((source, _)) = self.graph.graph.edge_endpoints(index) {
+                        self.graph.graph.remove_edge(index);
+                        hash::touch(self.graph, source);
+                    }
+                }
+                Staged::Alias {
+                    node,
+                    previous,
+                    history_len,
+                } => alias::restore(self.graph, node, previous, history_len),
+                Staged::Content { node, previous } => {
+                    self.graph.replace_node(node, previous);
+                    hash::touch(self.graph, node);
+                }
+            }
+        }
+        telemetry::mutated(self.graph, "rollback");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        time::Duration,
+    };
+
+    use jiff::{Timestamp, ToSpan};
+
+    use crate::{Edge, Node, Permissions, Terfer, TerferGraph};
+
+    fn node(id: &str) -> Node {
+        Node::new(id.to_string(), format!("Node {id}"))
+    }
+
+    fn edge(id: &str) -> Edge {
+        Edge::new(id.to_string())
+    }
+
+    #[test]
+    fn commit_keeps_changes() {
+        let mut tg = TerferGraph::new_tg();
+        let existing = tg.add_node(node("1"));
+
+        let mut session = tg.session();
+        let added = session.add_node(node("2"));
+        session.add_edge(existing, added, edge("1")).unwrap();
+        session.commit();
+
+        assert_eq!(tg.node_count(), 2);
+        assert_eq!(tg.edge_count(), 1);
+    }
+
+    #[test]
+    fn drop_rolls_back() {
+        let mut tg = TerferGraph::new_tg();
+        let existing = tg.add_node(node("1"));
+
+        {
+            let mut session = tg.session();
+            let added = session.add_node(node("2"));
+            session.add_edge(existing, added, edge("1")).unwrap();
+            assert_eq!(session.node_count(), 2);
+        }
+
+        assert_eq!(tg.node_count(), 1);
+        assert_eq!(tg.edge_count(), 0);
+    }
+
+    #[test]
+    fn panic_rolls_back() {
+        let mut tg = TerferGraph::new_tg();
+        tg.add_node(node("1"));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut session = tg.session();
+            session.add_node(node("2"));
+            panic!("multi-step operation failed");
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(tg.node_count(), 1);
+    }
+
+    #[test]
+    fn drop_restores_aliases() {
+        let mut tg = TerferGraph::new_tg();
+        let existing = tg.add_node(node("1"));
+        tg.set_alias(existing, "root").unwrap();
+
+        {
+            let mut session = tg.session();
+            session.set_alias(existing, "renamed").unwrap();
+            let added = session.add_node(node("2"));
+            session.set_alias(added, "root").unwrap();
+        }
+
+        assert_eq!(tg.node_by_alias("root"), Some(existing));
+        assert_eq!(tg.node_by_alias("renamed"), None);
+        assert!(tg.node(existing).unwrap().alias_history().is_empty());
+    }
+
+    #[test]
+    fn drop_restores_values() {
+        let mut tg = TerferGraph::new_tg();
+        let existing = tg.add_node(node("1"));
+        tg.expire_at(existing, Timestamp::now() + 1.hours())
+            .unwrap();
+
+        {
+            let mut session = tg.session();
+            session.update(existing, "edited".to_string()).unwrap();
+            session
+                .record(existing, "approved", "edited".to_string())
+                .unwrap();
+            session.delete(existing).unwrap();
+            assert!(session.node(existing).unwrap().is_deleted());
+        }
+
+        let restored = tg.node(existing).unwrap();
+        assert!(!restored.is_deleted());
+        assert_eq!(restored.value(), "Node 1");
+        assert_eq!(restored.history().len(), 1);
+        assert_eq!(tg.expirations.len(), 1);
+    }
+
+    #[test]
+    fn drop_restores_settings_and_alias_edges() {
+        let mut tg = TerferGraph::new_tg();
+        let a = tg.add_node(node("1"));
+        let b = tg.add_node(node("2"));
+        tg.set_alias(a, "a").unwrap();
+        tg.set_alias(b, "b").unwrap();
+        let at = Timestamp::now() + 1.hours();
+        tg.expire_at(a, at).unwrap();
+
+        {
+            let mut session = tg.session();
+            session.expire_at(a, at + 1.hours()).unwrap();
+            session
+                .set_node_coalesce_window(a, Some(Duration::from_secs(5)))
+                .unwrap();
+            session
+                .set_owner(a, Some("alice".to_string()), Permissions::PRIVATE)
+                .unwrap();
+            session.connect_by_alias("a", "b", edge("1")).unwrap();
+            assert_eq!(session.edge_count(), 1);
+        }
+
+        let restored = tg.node(a).unwrap();
+        assert_eq!(restored.expires_at(), Some(at));
+        assert_eq!(restored.coalesce_window, None);
+        assert_eq!(restored.owner(), None);
+        assert_eq!(restored.permissions(), Permissions::default());
+        assert_eq!(tg.expirations.iter().collect::<Vec<_>>(), [&(at, a)]);
+        assert_eq!(tg.edge_count(), 0);
+    }
+}
@@ -0,0 +1,287 @@
+//! Optional instrumentation. With the `tracing` feature every mutation runs
+//! inside a span and reports its outcome as an event. With the `metrics`
+//! feature it bumps `terfer_operations_total` or `terfer_errors_total`,
+//! records how long it took in `terfer_operation_duration_seconds` and
+//! refreshes the `terfer_nodes` and `terfer_edges` gauges, and waits for the
+//! hash cache lock are recorded in `terfer_lock_wait_seconds`. Without either
+//! feature everything here compiles away.
+//!
+//! The gauges are labelled with a `graph` id that is unique within the
+//! process, so forks and extracted graphs do not overwrite the graph they
+//! came from.
+
+use std::{
+    fmt,
+    sync::{LockResult, Mutex, MutexGuard},
+};
+
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+use crate::TerferGraph;
+
+/// Covers one operation, from [`span`] until it is dropped.
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::EnteredSpan,
+    #[cfg(feature = "metrics")]
+    operation: &'static str,
+    #[cfg(feature = "metrics")]
+    started: Instant,
+}
+
+pub(crate) fn span(operation: &'static str) -> Span {
+    #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+    let _ = operation;
+
+    Span {
+        #[cfg(feature = "tracing")]
+        _entered: tracing::debug_span!("terfer", operation).entered(),
+        #[cfg(feature = "metrics")]
+        operation,
+        #[cfg(feature = "metrics")]
+        started: Instant::now(),
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for Span {
+    fn drop(&mut self) {
+        metrics::histogram!("terfer_operation_duration_seconds", "operation" => self.operation)
+            .record(self.started.elapsed());
+    }
+}
+
+/// Locks `mutex`, recording how long that took against `name`.
+pub(crate) fn lock<'a, T>(
+    name: &'static str,
+    mutex: &'a Mutex<T>,
+) -> LockResult<MutexGuard<'a, T>> {
+    #[cfg(feature = "metrics")]
+    let started = Instant::now();
+
+    let guard = mutex.lock();
+
+    #[cfg(feature = "metrics")]
+    metrics::histogram!("terfer_lock_wait_seconds", "lock" => name).record(started.elapsed());
+    #[cfg(not(feature = "metrics"))]
+    let _ = name;
+
+    guard
+}
+
+/// Counts an applied mutation and refreshes the size gauges.
+pub(crate) fn mutated(graph: &TerferGraph, operation: &'static str) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        operation,
+        nodes = graph.graph.node_count(),
+        edges = graph.graph.edge_count(),
+        "mutation applied"
+    );
+
+    #[cfg(feature = "metrics")]
+    {
+        metrics::counter!("terfer_operations_total", "operation" => operation).increment(1);
+        let id = graph.id.0.to_string();
+        metrics::gauge!("terfer_nodes", "graph" => id.clone()).set(graph.graph.node_count() as f64);
+        metrics::gauge!("terfer_edges", "graph" => id).set(graph.graph.edge_count() as f64);
+    }
+
+    #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+    let _ = (graph, operation);
+}
+
+/// Counts a mutation that was rejected and left the graph unchanged.
+pub(crate) fn failed(operation: &'static str, error: &dyn fmt::Display) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(operation, %error, "mutation failed");
+
+    #[cfg(feature = "metrics")]
+    metrics::counter!("terfer_errors_total", "operation" => operation).increment(1);
+
+    #[cfg(not(feature = "tracing"))]
+    let _ = error;
+    #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+    let _ = operation;
+}
+
+pub(crate) fn outcome<T, E: fmt::Display>(
+    graph: &TerferGraph,
+    operation: &'static str,
+    result: Result<T, E>,
+) -> Result<T, E> {
+    match &result {
+        Ok(_) => mutated(graph, operation),
+        Err(error) => failed(operation, error),
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics_count_operations() {
+        use std::{
+            collections::HashMap,
+            sync::{
+                atomic::{AtomicU64, Ordering},
+                Arc, Mutex,
+            },
+        };
+
+        use metrics::{
+            Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, SharedString, Unit,
+        };
+        use petgraph::graph::NodeIndex;
+
+        use crate::{HashScope, Node, Terfer, TerferGraph};
+
+        #[derive(Default)]
+        struct Recorder(Mutex<HashMap<String, Arc<AtomicU64>>>);
+
+        impl Recorder {
+            fn handle(&self, key: &Key) -> Arc<AtomicU64> {
+                let mut name = key.name().to_string();
+                for label in key.labels() {
+                    name += &format!(" {}={}", label.key(), label.value());
+                }
+                Arc::clone(self.0.lock().unwrap().entry(name).or_default())
+            }
+
+            fn get(&self, name: &str) -> u64 {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .get(name)
+                    .map_or(0, |value| value.load(Ordering::Relaxed))
+            }
+        }
+
+        impl metrics::Recorder for Recorder {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                Counter::from_arc(self.handle(key))
+            }
+
+            fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::from_arc(self.handle(key))
+            }
+
+            fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+                Histogram::from_arc(Arc::new(Samples(self.handle(key))))
+            }
+        }
+
+        /// Counts samples rather than keeping them.
+        struct Samples(Arc<AtomicU64>);
+
+        impl HistogramFn for Samples {
+            fn record(&self, _: f64) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let recorder = Recorder::default();
+        let (graph, fork) = metrics::with_local_recorder(&recorder, || {
+            let mut tg = TerferGraph::new_tg();
+            tg.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+            tg.session()
+                .add_node(Node::new("2".to_string(), "Node 2".to_string()));
+            assert!(tg.delete(NodeIndex::new(1)).is_err());
+            tg.content_hash(HashScope::Live);
+
+            let mut fork = tg.fork();
+            fork.add_node(Node::new("2".to_string(), "Node 2".to_string()));
+            (tg.id.0, fork.id.0)
+        });
+
+        assert_eq!(
+            recorder.get("terfer_operations_total operation=add_node"),
+            3
+        );
+        assert_eq!(
+            recorder.get("terfer_operations_total operation=rollback"),
+            1
+        );
+        assert_eq!(recorder.get("terfer_errors_total operation=delete"), 1);
+        assert_eq!(
+            recorder.get("terfer_operation_duration_seconds operation=add_node"),
+            3
+        );
+        assert_eq!(recorder.get("terfer_lock_wait_seconds lock=hashes"), 2);
+        let nodes = |id| f64::from_bits(recorder.get(&format!("terfer_nodes graph={id}")));
+        assert_eq!(nodes(graph), 1.0);
+        assert_eq!(nodes(fork), 2.0);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_reports_outcomes() {
+        use std::{
+            fmt::{self, Write},
+            sync::{Arc, Mutex},
+        };
+
+        use petgraph::graph::NodeIndex;
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Metadata, Subscriber,
+        };
+
+        use crate::{Node, Terfer, TerferGraph};
+
+        #[derive(Clone, Default)]
+        struct Events(Arc<Mutex<Vec<String>>>);
+
+        struct Fields(String);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                let _ = write!(self.0, "{}={:?} ", field.name(), value);
+            }
+        }
+
+        impl Subscriber for Events {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields(String::new());
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0);
+            }
+
+            fn enter(&self, _: &span::Id) {}
+
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let events = Events::default();
+        tracing::subscriber::with_default(events.clone(), || {
+            let mut tg = TerferGraph::new_tg();
+            let node = tg.add_node(Node::new("1".to_string(), "Node 1".to_string()));
+            tg.set_node_coalesce_window(node, None).unwrap();
+            assert!(tg.delete(NodeIndex::new(1)).is_err());
+        });
+
+        let events = events.0.lock().unwrap();
+        assert_eq!(events.len(), 3);
+        assert!(events[0].starts_with("message=mutation applied operation=\"add_node\""));
+        assert!(events[1].contains("operation=\"set_coalesce_window\""));
+        assert!(events[2].starts_with("message=mutation failed operation=\"delete\""));
+    }
+}
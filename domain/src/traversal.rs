@@ -0,0 +1,265 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use petgraph::{
+    graph::NodeIndex,
+    visit::{VisitMap, Visitable},
+    Graph,
+};
+
+use crate::{acl, Edge, Node, TerferGraph};
+
+/// How many edges of one node are scanned between looks at the clock, which
+/// would cost more than the scan itself if read for every edge.
+const TIMEOUT_CHECK_INTERVAL: usize = 1024;
+
+/// Bounds for [`Terfer::traverse`](crate::Terfer::traverse). `None` means
+/// unbounded.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraversalOptions {
+    pub max_depth: Option<usize>,
+    pub max_nodes: Option<usize>,
+    /// How many outgoing edges of a single node are followed. The rest of a
+    /// hub's edges are skipped rather than scanned.
+    pub max_degree: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+/// The bound that cut a traversal short.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Depth,
+    Nodes,
+    Degree,
+    Timeout,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Node(NodeIndex),
+    /// Always the last item; the nodes before it are only part of the result.
+    Truncated(Limit),
+}
+
+/// Breadth-first walk along outgoing edges. Each reachable node is yielded
+/// once, so cycles terminate on their own; the options bound the work done on
+/// graphs that are simply too large. Depth and degree limits skip what lies
+/// beyond them and are reported once the rest of the walk is done; node and
+/// time limits end the walk where it is. Soft-deleted nodes and nodes the
+/// principal cannot read are not entered.
+pub struct Traversal<'a> {
+    graph: &'a TerferGraph,
+    options: TraversalOptions,
+    queue: VecDeque<(NodeIndex, usize)>,
+    visited: <Graph<Arc<Node>, Edge> as Visitable>::Map,
+    started: Instant,
+    yielded: usize,
+    /// The first depth or degree limit that left something out.
+    skipped: Option<Limit>,
+    timed_out: bool,
+    finished: bool,
+}
+
+impl<'a> Traversal<'a> {
+    pub(crate) fn new(graph: &'a TerferGraph, start: NodeIndex, options: TraversalOptions) -> Self {
+        let mut visited = graph.graph.visit_map();
+        let mut queue = VecDeque::new();
+        if graph
+            .graph
+            .node_weight(start)
+            .is_some_and(|node| !node.is_deleted() && acl::can_read(graph, node))
+        {
+            visited.visit(start);
+            queue.push_back((start, 0));
+        }
+
+        Traversal {
+            graph,
+            options,
+            queue,
+            visited,
+            started: Instant::now(),
+            yielded: 0,
+            skipped: None,
+            timed_out: false,
+            finished: false,
+        }
+    }
+
+    fn truncate(&mut self, limit: Limit) -> Option<Step> {
+        self.finished = true;
+        Some(Step::Truncated(limit))
+    }
+
+    fn past_timeout(&self) -> bool {
+        self.options
+            .timeout
+            .is_some_and(|timeout| self.started.elapsed() >= timeout)
+    }
+}
+
+impl Iterator for Traversal<'_> {
+    type Item = Step;
+
+    fn next(&mut self) -> Option<Step> {
+        if self.finished {
+            return None;
+        }
+
+        if self.timed_out {
+            return self.truncate(Limit::Timeout);
+        }
+        if self.queue.is_empty() {
+            self.finished = true;
+            return self.skipped.map(Step::Truncated);
+        }
+        if self
+            .options
+            .max_nodes
+            .is_some_and(|max_nodes| self.yielded >= max_nodes)
+        {
+            return self.truncate(Limit::Nodes);
+        }
+        if self.past_timeout() {
+            return self.truncate(Limit::Timeout);
+        }
+
+        let (node, depth) = self.queue.pop_front()?;
+        let at_max_depth = self
+            .options
+            .max_depth
+            .is_some_and(|max_depth| depth >= max_depth);
+        for (scanned, neighbor) in self.graph.graph.neighbors(node).enumerate() {
+            if self
+                .options
+                .max_degree
+                .is_some_and(|max_degree| scanned >= max_degree)
+            {
+                self.skipped.get_or_insert(Limit::Degree);
+                break;
+            }
+            if scanned % TIMEOUT_CHECK_INTERVAL == TIMEOUT_CHECK_INTERVAL - 1 && self.past_timeout()
+            {
+                self.timed_out = true;
+                break;
+            }
+            let weight = &self.graph.graph[neighbor];
+            if weight.is_deleted() || !acl::can_read(self.graph, weight) {
+                continue;
+            }
+            if at_max_depth {
+                if !self.visited.is_visited(&neighbor) {
+                    self.skipped.get_or_insert(Limit::Depth);
+                }
+            } else if self.visited.visit(neighbor) {
+                self.queue.push_back((neighbor, depth + 1));
+            }
+        }
+
+        self.yielded += 1;
+        Some(Step::Node(node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use petgraph::graph::NodeIndex;
+
+    use super::{Limit, Step, TraversalOptions};
+    use crate::{Edge, Node, Terfer, TerferGraph};
+
+    /// Builds a cycle of `len` nodes: 0 -> 1 -> ... -> len - 1 -> 0.
+    fn cycle(len: usize) -> TerferGraph {
+        let mut tg = TerferGraph::new_tg();
+        let nodes: Vec<_> = (0..len)
+            .map(|i| tg.add_node(Node::new(i.to_string(), format!("Node {i}"))))
+            .collect();
+        for i in 0..len {
+            tg.add_edge(nodes[i], nodes[(i + 1) % len], Edge::new(i.to_string()))
+                .unwrap();
+        }
+        tg
+    }
+
+    #[test]
+    fn cycle_terminates_without_limits() {
+        let tg = cycle(4);
+        let steps: Vec<_> = tg
+            .traverse(NodeIndex::new(0), TraversalOptions::default())
+            .collect();
+        assert_eq!(steps.len(), 4);
+        assert!(steps.iter().all(|step| matches!(step, Step::Node(_))));
+    }
+
+    #[test]
+    fn depth_limit_is_reported() {
+        let tg = cycle(4);
+        let options = TraversalOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        let steps: Vec<_> = tg.traverse(NodeIndex::new(0), options).collect();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps.last(), Some(&Step::Truncated(Limit::Depth)));
+    }
+
+    #[test]
+    fn node_limit_is_reported() {
+        let tg = cycle(4);
+        let options = TraversalOptions {
+            max_nodes: Some(2),
+            ..Default::default()
+        };
+        let steps: Vec<_> = tg.traverse(NodeIndex::new(0), options).collect();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps.last(), Some(&Step::Truncated(Limit::Nodes)));
+    }
+
+    #[test]
+    fn degree_limit_is_reported() {
+        // A hub with edges to four leaves, the last of which leads on. Edges
+        // are scanned newest first.
+        let mut tg = TerferGraph::new_tg();
+        let nodes: Vec<_> = (0..6)
+            .map(|i| tg.add_node(Node::new(i.to_string(), format!("Node {i}"))))
+            .collect();
+        for i in 1..5 {
+            tg.add_edge(nodes[0], nodes[i], Edge::new(i.to_string()))
+                .unwrap();
+        }
+        tg.add_edge(nodes[4], nodes[5], Edge::new("5".to_string()))
+            .unwrap();
+
+        let options = TraversalOptions {
+            max_degree: Some(2),
+            ..Default::default()
+        };
+        let steps: Vec<_> = tg.traverse(nodes[0], options).collect();
+        assert_eq!(
+            steps,
+            [
+                Step::Node(nodes[0]),
+                Step::Node(nodes[4]),
+                Step::Node(nodes[3]),
+                Step::Node(nodes[5]),
+                Step::Truncated(Limit::Degree),
+            ]
+        );
+    }
+
+    #[test]
+    fn timeout_is_reported() {
+        let tg = cycle(4);
+        let options = TraversalOptions {
+            timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let steps: Vec<_> = tg.traverse(NodeIndex::new(0), options).collect();
+        assert_eq!(steps, [Step::Truncated(Limit::Timeout)]);
+    }
+}